@@ -0,0 +1,633 @@
+// Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use serenity::{
+    all::{ChannelId, UserId},
+    futures::future::join_all,
+};
+use tauri::{AppHandle, Emitter, Manager, RunEvent, State};
+use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
+use tauri_plugin_shell::ShellExt;
+use tauri_plugin_updater::UpdaterExt;
+use tokio::sync::Mutex;
+
+use crate::vc::{
+    backend::SerenityBackend,
+    bot_pool::BotPool,
+    config::{ConfigManager, ListenerConfig, MatchPreset, SessionConfig, SpeakerConfig},
+    error::VcError,
+    events::EventEmitter,
+    overlay::Overlay,
+    relay::RelayReport,
+    schedule::{self, Schedule},
+    status::{BotRole, BotStatus},
+    types::{PubIdentify, Route, SessionId, SubIdentify, VoiceChannels},
+    vc_client::VC,
+};
+
+// VoiceManagerとBotの状態のイベントをフロントエンドに送る
+struct TauriEmitter(AppHandle);
+
+impl EventEmitter for TauriEmitter {
+    fn emit(&self, event: &str, payload: serde_json::Value) {
+        if let Err(e) = self.0.emit(event, payload) {
+            log::error!("failed to emit {}: {:?}", event, e);
+        }
+    }
+}
+
+struct Storage {
+    // configのsessions順
+    session_ids: Vec<SessionId>,
+    vcs: HashMap<SessionId, Arc<Mutex<VC>>>,
+    config_manager: Mutex<ConfigManager>,
+    // 終了処理を始めたか
+    shutting_down: AtomicBool,
+}
+
+// 終了時にBotの退出を待つ上限
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Storage {
+    fn vc(&self, session_id: &SessionId) -> Result<&Arc<Mutex<VC>>, VcError> {
+        self.vcs
+            .get(session_id)
+            .ok_or_else(|| VcError::UnknownSession(session_id.clone()))
+    }
+    // 全sessionのBotをVCから退出させてgatewayを閉じる
+    async fn shutdown(&self) {
+        let vcs = self
+            .vcs
+            .values()
+            .map(|vc| async move { vc.lock().await.shutdown().await });
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, join_all(vcs))
+            .await
+            .is_err()
+        {
+            log::warn!("shutdown timed out");
+        }
+    }
+}
+
+fn parse_channel(ch: &str) -> Result<ChannelId, VcError> {
+    match ch.parse::<u64>() {
+        Ok(id) if id != 0 => Ok(ChannelId::new(id)),
+        _ => Err(VcError::InvalidChannelId(ch.to_owned())),
+    }
+}
+
+// 未選択("")のchannelは除く
+fn parse_channels<K: Eq + std::hash::Hash>(
+    chs: HashMap<K, String>,
+) -> Result<HashMap<K, ChannelId>, VcError> {
+    chs.into_iter()
+        .filter(|(_, ch)| !ch.is_empty())
+        .map(|(identify, ch)| Ok((identify, parse_channel(&ch)?)))
+        .collect()
+}
+
+#[tauri::command]
+async fn get_sessions(storage: State<'_, Storage>) -> Result<Vec<SessionId>, VcError> {
+    Ok(storage.session_ids.clone())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+async fn get_voice_channels(
+    session_id: SessionId,
+    storage: State<'_, Storage>,
+) -> Result<VoiceChannels, VcError> {
+    let vc = storage.vc(&session_id)?.lock().await;
+    vc.get_voice_channels().await
+}
+
+#[tauri::command(rename_all = "snake_case")]
+async fn update_volume(
+    session_id: SessionId,
+    // 省略時はtrackによらないユーザーの音量だけを変える
+    track: Option<PubIdentify>,
+    user_id: UserId,
+    volume: f32,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    {
+        let vc = storage.vc(&session_id)?.lock().await;
+        vc.update_volume(track.as_ref(), user_id, volume).await;
+    }
+    {
+        let cfg_manager = storage.config_manager.lock().await;
+        cfg_manager.update_volume(&session_id, track.as_ref(), user_id, volume)?;
+    }
+    Ok(())
+}
+#[tauri::command(rename_all = "snake_case")]
+async fn update_is_listening(
+    session_id: SessionId,
+    identify: PubIdentify,
+    is_listening: bool,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    let vc = storage.vc(&session_id)?.lock().await;
+    vc.update_is_listening(identify, is_listening).await
+}
+#[tauri::command(rename_all = "snake_case")]
+async fn get_tracks(
+    session_id: SessionId,
+    storage: State<'_, Storage>,
+) -> Result<Vec<PubIdentify>, VcError> {
+    let vc = storage.vc(&session_id)?.lock().await;
+    Ok(vc.tracks())
+}
+#[tauri::command(rename_all = "snake_case")]
+async fn get_listeners(
+    session_id: SessionId,
+    storage: State<'_, Storage>,
+) -> Result<Vec<SubIdentify>, VcError> {
+    let vc = storage.vc(&session_id)?.lock().await;
+    Ok(vc.listeners())
+}
+// bot_poolからBotを割り当ててtrackを増やす
+#[tauri::command(rename_all = "snake_case")]
+async fn add_track(
+    session_id: SessionId,
+    track: PubIdentify,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    let speaker = SpeakerConfig {
+        track,
+        api: None,
+        guild_id: None,
+        anchor: None,
+    };
+    {
+        let mut vc = storage.vc(&session_id)?.lock().await;
+        vc.add_speaker(&speaker).await?;
+    }
+    {
+        let cfg_manager = storage.config_manager.lock().await;
+        cfg_manager.add_speaker(&session_id, speaker)?;
+    }
+    Ok(())
+}
+#[tauri::command(rename_all = "snake_case")]
+async fn add_listener(
+    session_id: SessionId,
+    name: SubIdentify,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    let listener = ListenerConfig {
+        name,
+        api: None,
+        guild_id: None,
+    };
+    {
+        let mut vc = storage.vc(&session_id)?.lock().await;
+        vc.add_listener(&listener).await?;
+    }
+    {
+        let cfg_manager = storage.config_manager.lock().await;
+        cfg_manager.add_listener(&session_id, listener)?;
+    }
+    Ok(())
+}
+// 各Botの接続状態
+#[tauri::command(rename_all = "snake_case")]
+async fn get_status(
+    session_id: SessionId,
+    storage: State<'_, Storage>,
+) -> Result<Vec<BotStatus>, VcError> {
+    let vc = storage.vc(&session_id)?.lock().await;
+    Ok(vc.get_status())
+}
+// trackとlistenerごとの遅延と捨てた音声の数
+#[tauri::command(rename_all = "snake_case")]
+async fn get_relay_stats(
+    session_id: SessionId,
+    storage: State<'_, Storage>,
+) -> Result<RelayReport, VcError> {
+    let vc = storage.vc(&session_id)?.lock().await;
+    Ok(vc.get_relay_stats().await)
+}
+#[tauri::command(rename_all = "snake_case")]
+async fn get_routes(
+    session_id: SessionId,
+    storage: State<'_, Storage>,
+) -> Result<Vec<Route>, VcError> {
+    let vc = storage.vc(&session_id)?.lock().await;
+    Ok(vc.get_routes().await)
+}
+#[tauri::command(rename_all = "snake_case")]
+async fn update_route(
+    session_id: SessionId,
+    track: PubIdentify,
+    listener: SubIdentify,
+    gain: f32,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    {
+        let vc = storage.vc(&session_id)?.lock().await;
+        vc.update_route(track.clone(), listener.clone(), gain)
+            .await?;
+    }
+    {
+        let cfg_manager = storage.config_manager.lock().await;
+        let route = Route {
+            track,
+            listener,
+            gain,
+        };
+        cfg_manager.update_route(&session_id, route)?;
+    }
+    Ok(())
+}
+#[tauri::command(rename_all = "snake_case")]
+async fn join(
+    session_id: SessionId,
+    speaker_chs: HashMap<PubIdentify, String>,
+    listener_chs: HashMap<SubIdentify, String>,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    let mut vc = storage.vc(&session_id)?.lock().await;
+    let pub_infos = parse_channels(speaker_chs)?;
+    let sub_infos = parse_channels(listener_chs)?;
+    vc.join(pub_infos, sub_infos).await
+}
+
+// Join中のまま1体だけチャンネルを変える
+#[tauri::command(rename_all = "snake_case")]
+async fn switch_channel(
+    session_id: SessionId,
+    role: BotRole,
+    name: String,
+    channel_id: String,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    let channel_id = parse_channel(&channel_id)?;
+    let mut vc = storage.vc(&session_id)?.lock().await;
+    vc.switch_channel(role, &name, channel_id).await
+}
+
+#[tauri::command(rename_all = "snake_case")]
+async fn get_presets(
+    session_id: SessionId,
+    storage: State<'_, Storage>,
+) -> Result<Vec<MatchPreset>, VcError> {
+    let cfg_manager = storage.config_manager.lock().await;
+    cfg_manager
+        .get_cfg()
+        .sessions
+        .into_iter()
+        .find(|session| session.id == session_id)
+        .map(|session| session.presets)
+        .ok_or(VcError::UnknownSession(session_id))
+}
+
+// 今選んでいるチャンネルなどをpresetとして保存する(同じ名前は上書き)
+#[tauri::command(rename_all = "snake_case")]
+async fn save_preset(
+    session_id: SessionId,
+    name: String,
+    speaker_chs: HashMap<PubIdentify, String>,
+    listener_chs: HashMap<SubIdentify, String>,
+    labels: HashMap<PubIdentify, String>,
+    listening: Vec<PubIdentify>,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    storage.vc(&session_id)?;
+    let preset = MatchPreset {
+        name,
+        speakers: parse_channels(speaker_chs)?,
+        listeners: parse_channels(listener_chs)?,
+        labels,
+        listening,
+    };
+    let cfg_manager = storage.config_manager.lock().await;
+    cfg_manager.save_preset(&session_id, preset)?;
+    Ok(())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+async fn delete_preset(
+    session_id: SessionId,
+    name: String,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    storage.vc(&session_id)?;
+    let cfg_manager = storage.config_manager.lock().await;
+    if !cfg_manager.delete_preset(&session_id, &name)? {
+        return Err(VcError::UnknownPreset(name));
+    }
+    Ok(())
+}
+
+// presetのチャンネルにJoin(Join中なら移動)する
+#[tauri::command(rename_all = "snake_case")]
+async fn apply_preset(
+    session_id: SessionId,
+    name: String,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    let preset = {
+        let cfg_manager = storage.config_manager.lock().await;
+        cfg_manager
+            .get_cfg()
+            .sessions
+            .into_iter()
+            .find(|session| session.id == session_id)
+            .and_then(|session| session.presets.into_iter().find(|p| p.name == name))
+            .ok_or(VcError::UnknownPreset(name))?
+    };
+    let mut vc = storage.vc(&session_id)?.lock().await;
+    vc.apply_preset(&preset).await
+}
+
+#[tauri::command(rename_all = "snake_case")]
+async fn get_anchors(
+    session_id: SessionId,
+    storage: State<'_, Storage>,
+) -> Result<HashMap<PubIdentify, UserId>, VcError> {
+    let vc = storage.vc(&session_id)?.lock().await;
+    Ok(vc.get_anchors())
+}
+
+// このユーザーが別のVCに移ったらtrackのBotも移す(user_idがnullなら追従しない)
+#[tauri::command(rename_all = "snake_case")]
+async fn set_anchor(
+    session_id: SessionId,
+    track: PubIdentify,
+    user_id: Option<UserId>,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    {
+        let vc = storage.vc(&session_id)?.lock().await;
+        vc.set_anchor(track.clone(), user_id)?;
+    }
+    {
+        let cfg_manager = storage.config_manager.lock().await;
+        cfg_manager.set_anchor(&session_id, &track, user_id)?;
+    }
+    Ok(())
+}
+
+// configのdiscoveryのルールで試合番号のチャンネルを探す(Joinはしない)
+#[tauri::command(rename_all = "snake_case")]
+async fn discover_match(
+    session_id: SessionId,
+    match_no: String,
+    storage: State<'_, Storage>,
+) -> Result<MatchPreset, VcError> {
+    let vc = storage.vc(&session_id)?.lock().await;
+    vc.discover_match(&match_no).await
+}
+
+// 保存していないチャンネル選択(discover_matchの結果など)にJoin(Join中なら移動)する
+#[tauri::command(rename_all = "snake_case")]
+async fn apply_channels(
+    session_id: SessionId,
+    preset: MatchPreset,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    let mut vc = storage.vc(&session_id)?.lock().await;
+    vc.apply_preset(&preset).await
+}
+
+// CSV/JSONのファイルかURL，start.gg/Challongeの大会から大会スケジュールを読み込む
+#[tauri::command(rename_all = "snake_case")]
+async fn import_schedule(
+    session_id: SessionId,
+    source: String,
+    storage: State<'_, Storage>,
+) -> Result<Schedule, VcError> {
+    let tracks = storage.vc(&session_id)?.lock().await.tracks();
+    let keys = storage.config_manager.lock().await.get_cfg().bracket;
+    let matches = schedule::load(&source, &tracks, &keys).await?;
+    let schedule = {
+        let mut vc = storage.vc(&session_id)?.lock().await;
+        vc.set_schedule(matches.clone());
+        vc.get_schedule()
+    };
+    {
+        let cfg_manager = storage.config_manager.lock().await;
+        cfg_manager.set_schedule(&session_id, matches)?;
+    }
+    Ok(schedule)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+async fn get_schedule(
+    session_id: SessionId,
+    storage: State<'_, Storage>,
+) -> Result<Schedule, VcError> {
+    let vc = storage.vc(&session_id)?.lock().await;
+    Ok(vc.get_schedule())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+async fn next_match(session_id: SessionId, storage: State<'_, Storage>) -> Result<(), VcError> {
+    let mut vc = storage.vc(&session_id)?.lock().await;
+    vc.next_match().await
+}
+
+#[tauri::command(rename_all = "snake_case")]
+async fn go_to_match(
+    session_id: SessionId,
+    match_id: String,
+    storage: State<'_, Storage>,
+) -> Result<(), VcError> {
+    let mut vc = storage.vc(&session_id)?.lock().await;
+    vc.go_to_match(&match_id).await
+}
+
+#[tauri::command(rename_all = "snake_case")]
+async fn leave(session_id: SessionId, storage: State<'_, Storage>) -> Result<(), VcError> {
+    let mut vc = storage.vc(&session_id)?.lock().await;
+    vc.leave().await;
+    Ok(())
+}
+
+const ENV_PATH: &str = "./.env";
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    // 読み込みエラーはsetupでDialogに出す
+    let cfg_manager = ConfigManager::new(ENV_PATH.to_string());
+
+    tauri::Builder::default()
+        .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_updater::Builder::new().build())
+        .setup(move |app| {
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = update(handle).await {
+                    log::error!("failed to update: {:?}", e);
+                }
+            });
+            let cfg_manager = match cfg_manager {
+                Ok(cfg_manager) => cfg_manager,
+                Err(e) => {
+                    eprintln!("Error loading config: {}", e);
+                    app.dialog()
+                        .message(format!(
+                            "設定ファイルの読み込みエラー！\n .envファイルを再確認してください\n{}",
+                            e
+                        ))
+                        .kind(MessageDialogKind::Error)
+                        .title("設定ファイルエラー")
+                        .blocking_show();
+                    return Err(e.into());
+                }
+            };
+            let cfg = cfg_manager.get_cfg();
+            let guild_id = cfg.guild_id;
+            let overlay = Arc::new(Overlay::new());
+            let bot_pool = Arc::new(BotPool::new(cfg.pooled_tokens()));
+            let backend = Arc::new(SerenityBackend::new());
+            let session_cfgs = cfg.sessions;
+            tauri::async_runtime::spawn(overlay.clone().serve(cfg.overlay_port));
+            // sessionごとにBot，音量，routesを持つ
+            let emitter = Arc::new(TauriEmitter(app.handle().clone()));
+            let mut sessions: Vec<(VC, SessionConfig)> = session_cfgs
+                .iter()
+                .map(|session| {
+                    let vc = VC::from_session(
+                        session,
+                        guild_id,
+                        overlay.clone(),
+                        bot_pool.clone(),
+                        backend.clone(),
+                        emitter.clone(),
+                    );
+                    (vc, session.clone())
+                })
+                .collect();
+            let res = tauri::async_runtime::block_on(async {
+                for (vc, session) in sessions.iter_mut() {
+                    vc.start_bot(&session.speakers, &session.listeners).await?;
+                }
+                Ok::<(), VcError>(())
+            });
+
+            // Stateの登録
+            let session_ids = sessions
+                .iter()
+                .map(|(vc, _)| vc.session_id().clone())
+                .collect();
+            let vcs: HashMap<SessionId, Arc<Mutex<VC>>> = sessions
+                .into_iter()
+                .map(|(vc, session)| (session.id, Arc::new(Mutex::new(vc))))
+                .collect();
+            // gatewayが落ちたBotを予備Botに切り替える
+            for vc in vcs.values() {
+                tauri::async_runtime::spawn(VC::supervise(vc.clone()));
+            }
+            app.manage(Storage {
+                session_ids,
+                vcs,
+                config_manager: Mutex::new(cfg_manager),
+                shutting_down: AtomicBool::new(false),
+            });
+            // API関連でエラーが発生した場合
+            if let Err(e) = res {
+                // Explorer表示
+                eprintln!("Error starting bot: {}", e);
+                let shell = app.handle().shell();
+                let pwd = std::env::current_dir().unwrap();
+                let exp_shell = shell
+                    .command("explorer.exe")
+                    .arg(pwd);
+                // Dialog表示
+                let res = app.dialog()
+                    .message("API認証エラー！\n .envファイルを再確認してください")
+                    .kind(MessageDialogKind::Error)
+                    .title("DiscordBot API認証エラー")
+                    .blocking_show();
+                if res {
+                    exp_shell.spawn().expect("failed to shell");
+                }
+                return Err("failed to start bot".to_string().into());
+            }
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            get_sessions,
+            join,
+            leave,
+            switch_channel,
+            get_presets,
+            save_preset,
+            delete_preset,
+            apply_preset,
+            get_anchors,
+            set_anchor,
+            discover_match,
+            apply_channels,
+            import_schedule,
+            get_schedule,
+            next_match,
+            go_to_match,
+            get_voice_channels,
+            get_tracks,
+            get_listeners,
+            add_track,
+            add_listener,
+            get_routes,
+            get_status,
+            get_relay_stats,
+            update_route,
+            update_volume,
+            update_is_listening
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let RunEvent::ExitRequested { api, code, .. } = event {
+                let storage = match app.try_state::<Storage>() {
+                    Some(storage) => storage,
+                    None => return,
+                };
+                if storage.shutting_down.swap(true, Ordering::SeqCst) {
+                    return;
+                }
+                // Botを退出させてから終了し直す
+                api.prevent_exit();
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    app.state::<Storage>().shutdown().await;
+                    app.exit(code.unwrap_or(0));
+                });
+            }
+        });
+}
+
+async fn update(app: AppHandle) -> tauri_plugin_updater::Result<()> {
+    if let Some(update) = app.updater()?.check().await? {
+        let mut downloaded = 0;
+        update
+            .download_and_install(
+                |chunk_length, content_length| {
+                    downloaded += chunk_length;
+                    println!("downloaded {downloaded} from {content_length:?}");
+                },
+                || {
+                    println!("download finished");
+                },
+            )
+            .await?;
+        println!("update installed");
+        // 再起動前にBotを退出させる
+        if let Some(storage) = app.try_state::<Storage>() {
+            storage.shutting_down.store(true, Ordering::SeqCst);
+            storage.shutdown().await;
+        }
+        app.restart();
+    }
+    Ok(())
+}
@@ -0,0 +1,153 @@
+// GUIなしでrelayを動かす(VPS向け)
+// cargo run --no-default-features --bin dvc-relay -- --track Track1=<channel_id> --listener Listener=<channel_id>
+use std::sync::Arc;
+
+use clap::Parser;
+use discordvoicecommv1_lib::vc::{
+    backend::SerenityBackend,
+    bot_pool::BotPool,
+    config::{ConfigManager, MatchPreset},
+    error::VcError,
+    events::StdoutEmitter,
+    overlay::Overlay,
+    types::{PubIdentify, SessionId, SubIdentify},
+    vc_client::VC,
+};
+use log::{error, info};
+use serenity::all::ChannelId;
+use tokio::sync::Mutex;
+use tracing::Level;
+
+#[derive(Parser)]
+#[command(about = "DiscordVoiceComm relay without GUI")]
+struct Args {
+    /// 設定ファイル(GUI版と同じ形式)
+    #[arg(long, default_value = "./.env")]
+    config: String,
+    /// 使うsessionのid
+    #[arg(long, default_value = "main")]
+    session: String,
+    /// trackが入るVC(TRACK=CHANNEL_ID)
+    #[arg(long = "track", value_parser = parse_channel)]
+    tracks: Vec<(String, ChannelId)>,
+    /// listenerが入るVC(LISTENER=CHANNEL_ID)
+    #[arg(long = "listener", value_parser = parse_channel)]
+    listeners: Vec<(String, ChannelId)>,
+    /// GUIで保存したpresetを使う(--track/--listenerで上書きできる)
+    #[arg(long)]
+    preset: Option<String>,
+    /// configのdiscoveryのルールでこの試合番号のチャンネルを探す
+    #[arg(long = "match", conflicts_with = "preset")]
+    match_no: Option<String>,
+}
+
+fn parse_channel(arg: &str) -> Result<(String, ChannelId), String> {
+    let (name, channel_id) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=CHANNEL_ID: {}", arg))?;
+    let channel_id = channel_id
+        .parse::<u64>()
+        .map_err(|e| format!("invalid channel id {}: {}", channel_id, e))?;
+    if channel_id == 0 {
+        return Err("channel id must not be 0".to_owned());
+    }
+    Ok((name.to_owned(), ChannelId::new(channel_id)))
+}
+
+// Ctrl+C，またはsystemdなどからのSIGTERMを待つ
+async fn wait_shutdown_signal() -> Result<(), VcError> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sigterm =
+            signal(SignalKind::terminate()).map_err(|e| VcError::Internal(e.to_string()))?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res.map_err(|e| VcError::Internal(e.to_string())),
+            _ = sigterm.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .map_err(|e| VcError::Internal(e.to_string()))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), VcError> {
+    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
+    let args = Args::parse();
+
+    let cfg = ConfigManager::new(args.config)?.get_cfg();
+    let session_id = SessionId(args.session);
+    let session = cfg
+        .sessions
+        .iter()
+        .find(|session| session.id == session_id)
+        .ok_or_else(|| VcError::UnknownSession(session_id.clone()))?;
+
+    let overlay = Arc::new(Overlay::new());
+    tokio::spawn(overlay.clone().serve(cfg.overlay_port));
+    let bot_pool = Arc::new(BotPool::new(cfg.pooled_tokens()));
+    let backend = Arc::new(SerenityBackend::new());
+    let mut vc = VC::from_session(
+        session,
+        cfg.guild_id,
+        overlay,
+        bot_pool,
+        backend,
+        Arc::new(StdoutEmitter),
+    );
+    vc.start_bot(&session.speakers, &session.listeners).await?;
+    let vc = Arc::new(Mutex::new(vc));
+    tokio::spawn(VC::supervise(vc.clone()));
+
+    {
+        let mut vc = vc.lock().await;
+        vc.wait_bots_ready().await?;
+        let mut preset = match (&args.preset, &args.match_no) {
+            (Some(name), _) => session
+                .presets
+                .iter()
+                .find(|preset| preset.name == *name)
+                .cloned()
+                .ok_or_else(|| VcError::UnknownPreset(name.clone()))?,
+            (None, Some(match_no)) => vc.discover_match(match_no).await?,
+            (None, None) => MatchPreset {
+                name: "cli".to_owned(),
+                ..Default::default()
+            },
+        };
+        for (track, channel_id) in args.tracks {
+            let track = PubIdentify(track);
+            // CLIで指定したtrackは聞き取る
+            if !preset.listening.contains(&track) {
+                preset.listening.push(track.clone());
+            }
+            preset.speakers.insert(track, channel_id);
+        }
+        for (listener, channel_id) in args.listeners {
+            preset.listeners.insert(SubIdentify(listener), channel_id);
+        }
+        // 入れなかったBotがあっても他のBotで中継を続ける
+        if let Err(e) = vc.apply_preset(&preset).await {
+            error!("join error: {}", e);
+        }
+    }
+    info!("{} joined, press Ctrl+C to leave", session_id);
+
+    wait_shutdown_signal().await?;
+    let mut vc = vc.lock().await;
+    let report = vc.get_relay_stats().await;
+    for stats in report.tracks {
+        info!("{}: dropped {} frames", stats.track, stats.dropped_frames);
+    }
+    for stats in report.listeners {
+        info!(
+            "{}: dropped {} frames, skipped {} frames to catch up",
+            stats.listener, stats.dropped_frames, stats.skipped_frames
+        );
+    }
+    // VCから退出してgatewayを閉じる
+    vc.shutdown().await;
+    Ok(())
+}
@@ -1,6 +1,24 @@
+pub mod backend;
+pub mod bot_pool;
+pub mod bracket;
 pub mod config;
+pub mod directory;
 pub mod dis_pub;
 pub mod dis_sub;
+pub mod discovery;
+pub mod error;
+pub mod events;
+pub mod join_session;
+// Discordを使わないbackend(テスト用)
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod overlay;
+pub mod reconnect;
+pub mod relay;
+pub mod rest;
+pub mod schedule;
+pub mod status;
 pub mod types;
 pub mod vc_client;
 pub mod voice_manager;
+pub mod volume;
@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use log::error;
+use serenity::{
+    all::{ChannelId, GuildChannel, GuildId, UserId},
+    async_trait,
+};
+
+use super::{
+    directory::MemberInfo,
+    dis_pub::Pub,
+    dis_sub::Sub,
+    error::VcError,
+    join_session::TaskScope,
+    relay::RelayMetrics,
+    rest::Rest,
+    status::{BotState, BotStatus, StatusReporter},
+    types::{
+        AnchorMovedSenderType, DeadBot, DeadBotSenderType, GuildChannelsType, JoinInfo,
+        PubIdentify, SubIdentify, VoiceManagerSenderType, VoiceReceiverType,
+    },
+};
+
+// 選手VC側のBot．VCの音声をVoiceManagerに送る
+#[async_trait]
+pub trait VoiceSource: Send + Sync {
+    fn identify(&self) -> &PubIdentify;
+    fn guild_id(&self) -> GuildId;
+    fn user_id(&self) -> Option<UserId>;
+    fn status(&self) -> BotStatus;
+    async fn is_ready(&self) -> bool;
+    async fn get_voice_channels(&self, guild_id: GuildId) -> Result<Vec<GuildChannel>, VcError>;
+    // VCとカテゴリ(1回のREST呼び出しで取る)
+    async fn get_channels(&self, guild_id: GuildId) -> Result<GuildChannelsType, VcError>;
+    // Join中に起動するtaskはtasksで起動する(leaveで止める)
+    // txが詰まっていて捨てた音声はmetricsに数える
+    async fn join(
+        &self,
+        join_info: JoinInfo,
+        tx: VoiceManagerSenderType,
+        metrics: Arc<RelayMetrics>,
+        tasks: &TaskScope,
+    ) -> Result<(), VcError>;
+    async fn leave(&self, guild_id: GuildId) -> Result<(), VcError>;
+    // Join中のまま別のチャンネルに移る
+    async fn switch_channel(&self, channel_id: ChannelId) -> Result<(), VcError>;
+    fn set_is_listening(&self, is_listening: bool);
+    fn is_listening(&self) -> bool;
+    // このユーザーが別のVCに移ったらanchor_txに通知する
+    fn set_anchor(&self, anchor: Option<UserId>);
+    fn anchor(&self) -> Option<UserId>;
+    // gatewayを閉じる(予備Botには切り替えない)
+    async fn shutdown(&self);
+}
+
+// 実況VC側のBot．VoiceManagerから来た音声を再生する
+#[async_trait]
+pub trait VoiceSink: Send + Sync {
+    fn identify(&self) -> &SubIdentify;
+    fn guild_id(&self) -> GuildId;
+    fn user_id(&self) -> Option<UserId>;
+    fn status(&self) -> BotStatus;
+    async fn is_ready(&self) -> bool;
+    async fn get_voice_channels(&self, guild_id: GuildId) -> Result<Vec<GuildChannel>, VcError>;
+    // VCとカテゴリ(1回のREST呼び出しで取る)
+    async fn get_channels(&self, guild_id: GuildId) -> Result<GuildChannelsType, VcError>;
+    async fn join(
+        &self,
+        join_info: JoinInfo,
+        rx: VoiceReceiverType,
+        tasks: &TaskScope,
+    ) -> Result<(), VcError>;
+    async fn leave(&self, guild_id: GuildId) -> Result<(), VcError>;
+    async fn switch_channel(&self, channel_id: ChannelId) -> Result<(), VcError>;
+    async fn shutdown(&self);
+}
+
+// Botの起動とユーザー情報の取得
+// 接続状態はstatusに書き込み，gatewayが止まったらdead_txに通知する
+#[async_trait]
+pub trait VoiceBackend: Send + Sync {
+    async fn start_source(
+        &self,
+        identify: PubIdentify,
+        guild_id: GuildId,
+        token: &str,
+        dead_tx: DeadBotSenderType,
+        anchor_tx: AnchorMovedSenderType,
+        status: StatusReporter,
+    ) -> Result<Box<dyn VoiceSource>, VcError>;
+    async fn start_sink(
+        &self,
+        identify: SubIdentify,
+        guild_id: GuildId,
+        token: &str,
+        dead_tx: DeadBotSenderType,
+        status: StatusReporter,
+    ) -> Result<Box<dyn VoiceSink>, VcError>;
+    // サーバーでのニックネームとアイコン(メンバーでなければユーザーの情報)
+    async fn member(&self, guild_id: GuildId, user_id: UserId) -> Result<MemberInfo, VcError>;
+}
+
+// Discord(serenity/songbird)のbackend
+pub struct SerenityBackend {
+    // ユーザー情報の取得用(guildごとに，そのguildで最後に起動したBotのものを使う)
+    rest: RwLock<HashMap<GuildId, Rest>>,
+}
+
+impl SerenityBackend {
+    pub fn new() -> Self {
+        SerenityBackend {
+            rest: RwLock::new(HashMap::new()),
+        }
+    }
+    fn set_rest(&self, guild_id: GuildId, rest: Rest) {
+        let mut writer = self.rest.write().unwrap();
+        writer.insert(guild_id, rest);
+    }
+}
+
+impl Default for SerenityBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl VoiceBackend for SerenityBackend {
+    async fn start_source(
+        &self,
+        identify: PubIdentify,
+        guild_id: GuildId,
+        token: &str,
+        dead_tx: DeadBotSenderType,
+        anchor_tx: AnchorMovedSenderType,
+        status: StatusReporter,
+    ) -> Result<Box<dyn VoiceSource>, VcError> {
+        let mut dis_pub = Pub::new(identify.clone(), guild_id, status.clone(), anchor_tx);
+        let mut client = match dis_pub.create_client(token).await {
+            Ok(client) => client,
+            Err(e) => {
+                status.set(BotState::Failed);
+                return Err(e);
+            }
+        };
+        if let Some(rest) = dis_pub.rest() {
+            self.set_rest(guild_id, rest);
+        }
+        tokio::spawn(async move {
+            if let Err(why) = client.start().await {
+                error!("Err with {} client channel: {:?}", identify, why);
+            }
+            // 終了時に閉じた場合は切り替えない
+            if status.get().state == BotState::Stopped {
+                return;
+            }
+            status.set(BotState::Failed);
+            // gatewayが止まったので予備Botに切り替える
+            let _ = dead_tx.send(DeadBot::Speaker(identify));
+        });
+        Ok(Box::new(dis_pub))
+    }
+    async fn start_sink(
+        &self,
+        identify: SubIdentify,
+        guild_id: GuildId,
+        token: &str,
+        dead_tx: DeadBotSenderType,
+        status: StatusReporter,
+    ) -> Result<Box<dyn VoiceSink>, VcError> {
+        let mut dis_sub = Sub::new(identify.clone(), guild_id, status.clone());
+        let mut client = match dis_sub.create_client(token).await {
+            Ok(client) => client,
+            Err(e) => {
+                status.set(BotState::Failed);
+                return Err(e);
+            }
+        };
+        if let Some(rest) = dis_sub.rest() {
+            self.set_rest(guild_id, rest);
+        }
+        tokio::spawn(async move {
+            if let Err(why) = client.start().await {
+                error!("Err with {} client channel: {:?}", identify, why);
+            }
+            if status.get().state == BotState::Stopped {
+                return;
+            }
+            status.set(BotState::Failed);
+            let _ = dead_tx.send(DeadBot::Listener(identify));
+        });
+        Ok(Box::new(dis_sub))
+    }
+    async fn member(&self, guild_id: GuildId, user_id: UserId) -> Result<MemberInfo, VcError> {
+        // 別のguildのBotではメンバーを取れないので，そのguildにいるBotで取る
+        let rest = self.rest.read().unwrap().get(&guild_id).cloned();
+        let rest =
+            rest.ok_or_else(|| VcError::Internal(format!("no bot in guild {}", guild_id)))?;
+        let member = rest
+            .call("get_member", |http| async move {
+                http.get_member(guild_id, user_id).await
+            })
+            .await;
+        match member {
+            Ok(member) => Ok(MemberInfo::from_member(&member)),
+            // サーバーから抜けたユーザーなど
+            Err(_) => {
+                let user = rest
+                    .call(
+                        "get_user",
+                        |http| async move { http.get_user(user_id).await },
+                    )
+                    .await?;
+                Ok(MemberInfo::from_user(&user))
+            }
+        }
+    }
+}
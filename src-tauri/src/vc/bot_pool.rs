@@ -0,0 +1,26 @@
+use std::sync::Mutex;
+
+use log::info;
+
+// configのbot_poolから空いているBotのTokenを割り当てる
+// 全sessionで共有する
+pub struct BotPool {
+    tokens: Mutex<Vec<String>>,
+}
+
+impl BotPool {
+    pub fn new(tokens: Vec<String>) -> Self {
+        BotPool {
+            tokens: Mutex::new(tokens),
+        }
+    }
+    pub fn acquire(&self) -> Option<String> {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.is_empty() {
+            return None;
+        }
+        let token = tokens.remove(0);
+        info!("bot assigned from pool ({} left)", tokens.len());
+        Some(token)
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{error::VcError, schedule::ScheduledMatch, types::PubIdentify};
+
+const CHALLONGE_API: &str = "https://api.challonge.com/v1/tournaments";
+const STARTGG_API: &str = "https://api.start.gg/gql/alpha";
+// start.ggの1ページ分の試合数(1回の問い合わせの複雑さの上限に収まる数)
+const STARTGG_PER_PAGE: u32 = 50;
+const STARTGG_QUERY: &str = "query EventSets($slug: String!, $page: Int!, $perPage: Int!) {
+  event(slug: $slug) {
+    sets(page: $page, perPage: $perPage, sortType: CALL_ORDER) {
+      pageInfo { totalPages }
+      nodes { identifier slots { entrant { name } } }
+    }
+  }
+}";
+
+// 大会サイトのAPIの認証情報
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BracketKeys {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challonge_api_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub startgg_token: Option<String>,
+}
+
+// challonge:<tournament>またはstartgg:<event slug>の形式ならAPIから読み込む
+// 大会サイトはDiscordのチャンネルを持っていないので，試合番号とチーム名だけを読む
+// (チャンネルは試合に移るときにdiscoveryのルールで探す)
+pub async fn load(
+    source: &str,
+    tracks: &[PubIdentify],
+    keys: &BracketKeys,
+) -> Option<Result<Vec<ScheduledMatch>, VcError>> {
+    if let Some(tournament) = source.strip_prefix("challonge:") {
+        return Some(load_challonge(tournament, tracks, keys).await);
+    }
+    if let Some(slug) = source.strip_prefix("startgg:") {
+        return Some(load_startgg(slug, tracks, keys).await);
+    }
+    None
+}
+
+fn missing_key(name: &str) -> VcError {
+    VcError::Schedule(format!("no {} in config", name))
+}
+
+async fn load_challonge(
+    tournament: &str,
+    tracks: &[PubIdentify],
+    keys: &BracketKeys,
+) -> Result<Vec<ScheduledMatch>, VcError> {
+    let api_key = keys
+        .challonge_api_key
+        .as_deref()
+        .ok_or_else(|| missing_key("challonge_api_key"))?;
+    let res = super::schedule::client()?
+        .get(format!("{}/{}.json", CHALLONGE_API, tournament))
+        .query(&[
+            ("api_key", api_key),
+            ("include_participants", "1"),
+            ("include_matches", "1"),
+        ])
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| VcError::Schedule(e.to_string()))?;
+    let text = res
+        .text()
+        .await
+        .map_err(|e| VcError::Schedule(e.to_string()))?;
+    parse_challonge(&text, tracks)
+}
+
+async fn load_startgg(
+    slug: &str,
+    tracks: &[PubIdentify],
+    keys: &BracketKeys,
+) -> Result<Vec<ScheduledMatch>, VcError> {
+    let token = keys
+        .startgg_token
+        .as_deref()
+        .ok_or_else(|| missing_key("startgg_token"))?;
+    let client = super::schedule::client()?;
+    let mut matches = Vec::new();
+    let mut page = 1;
+    loop {
+        let body = json!({
+            "query": STARTGG_QUERY,
+            "variables": { "slug": slug, "page": page, "perPage": STARTGG_PER_PAGE },
+        });
+        let res = client
+            .post(STARTGG_API)
+            .bearer_auth(token)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| VcError::Schedule(e.to_string()))?;
+        let text = res
+            .text()
+            .await
+            .map_err(|e| VcError::Schedule(e.to_string()))?;
+        let (sets, total_pages) = parse_startgg(&text, tracks)?;
+        matches.extend(sets);
+        if page >= total_pages {
+            break;
+        }
+        page += 1;
+    }
+    Ok(matches)
+}
+
+// 試合の順番にチーム名をtrackに割り当てる(trackより多いチームは無視する)
+fn to_match(id: String, teams: Vec<Option<String>>, tracks: &[PubIdentify]) -> ScheduledMatch {
+    let teams = tracks
+        .iter()
+        .zip(teams)
+        .filter_map(|(track, team)| Some((track.clone(), team?)))
+        .collect();
+    ScheduledMatch {
+        id,
+        speakers: HashMap::new(),
+        listeners: HashMap::new(),
+        teams,
+    }
+}
+
+// GET /v1/tournaments/{tournament}.json?include_participants=1&include_matches=1
+fn parse_challonge(text: &str, tracks: &[PubIdentify]) -> Result<Vec<ScheduledMatch>, VcError> {
+    #[derive(Deserialize)]
+    struct Response {
+        tournament: Tournament,
+    }
+    #[derive(Deserialize)]
+    struct Tournament {
+        #[serde(default)]
+        participants: Vec<ParticipantWrapper>,
+        #[serde(default)]
+        matches: Vec<MatchWrapper>,
+    }
+    #[derive(Deserialize)]
+    struct ParticipantWrapper {
+        participant: Participant,
+    }
+    #[derive(Deserialize)]
+    struct Participant {
+        id: u64,
+        name: String,
+        // グループステージの試合ではこちらのidが使われる
+        #[serde(default)]
+        group_player_ids: Vec<u64>,
+    }
+    #[derive(Deserialize)]
+    struct MatchWrapper {
+        #[serde(rename = "match")]
+        m: Match,
+    }
+    #[derive(Deserialize)]
+    struct Match {
+        identifier: String,
+        player1_id: Option<u64>,
+        player2_id: Option<u64>,
+        suggested_play_order: Option<u64>,
+    }
+    let res: Response = serde_json::from_str(text).map_err(|e| VcError::Schedule(e.to_string()))?;
+    let mut names = HashMap::new();
+    for ParticipantWrapper { participant } in res.tournament.participants {
+        for id in participant.group_player_ids {
+            names.insert(id, participant.name.clone());
+        }
+        names.insert(participant.id, participant.name);
+    }
+    let mut matches: Vec<Match> = res.tournament.matches.into_iter().map(|m| m.m).collect();
+    // 順番が決まっていない試合は最後
+    matches.sort_by_key(|m| m.suggested_play_order.unwrap_or(u64::MAX));
+    Ok(matches
+        .into_iter()
+        .map(|m| {
+            let teams = [m.player1_id, m.player2_id]
+                .into_iter()
+                .map(|id| id.and_then(|id| names.get(&id).cloned()))
+                .collect();
+            to_match(m.identifier, teams, tracks)
+        })
+        .collect())
+}
+
+// start.ggのGraphQL APIの応答(試合と全ページ数)
+fn parse_startgg(
+    text: &str,
+    tracks: &[PubIdentify],
+) -> Result<(Vec<ScheduledMatch>, u32), VcError> {
+    #[derive(Deserialize)]
+    struct Response {
+        data: Option<Data>,
+        #[serde(default)]
+        errors: Vec<GraphQlError>,
+    }
+    #[derive(Deserialize)]
+    struct GraphQlError {
+        message: String,
+    }
+    #[derive(Deserialize)]
+    struct Data {
+        event: Option<Event>,
+    }
+    #[derive(Deserialize)]
+    struct Event {
+        sets: Sets,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Sets {
+        page_info: PageInfo,
+        nodes: Vec<Set>,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct PageInfo {
+        total_pages: u32,
+    }
+    #[derive(Deserialize)]
+    struct Set {
+        identifier: String,
+        #[serde(default)]
+        slots: Vec<Slot>,
+    }
+    #[derive(Deserialize)]
+    struct Slot {
+        entrant: Option<Entrant>,
+    }
+    #[derive(Deserialize)]
+    struct Entrant {
+        name: String,
+    }
+    let res: Response = serde_json::from_str(text).map_err(|e| VcError::Schedule(e.to_string()))?;
+    if let Some(error) = res.errors.first() {
+        return Err(VcError::Schedule(error.message.clone()));
+    }
+    let sets = res
+        .data
+        .and_then(|data| data.event)
+        .ok_or_else(|| VcError::Schedule("event not found".to_owned()))?
+        .sets;
+    let matches = sets
+        .nodes
+        .into_iter()
+        .map(|set| {
+            let teams = set
+                .slots
+                .into_iter()
+                .map(|slot| slot.entrant.map(|entrant| entrant.name))
+                .collect();
+            to_match(set.identifier, teams, tracks)
+        })
+        .collect();
+    Ok((matches, sets.page_info.total_pages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracks() -> Vec<PubIdentify> {
+        vec![
+            PubIdentify("Track1".to_owned()),
+            PubIdentify("Track2".to_owned()),
+        ]
+    }
+
+    fn team(m: &ScheduledMatch, track: &str) -> Option<String> {
+        m.teams.get(&PubIdentify(track.to_owned())).cloned()
+    }
+
+    #[test]
+    fn challonge_matches_are_sorted_by_play_order() {
+        let text = r#"{"tournament": {
+            "participants": [
+                {"participant": {"id": 1, "name": "Alpha", "group_player_ids": [11]}},
+                {"participant": {"id": 2, "name": "Beta", "group_player_ids": []}},
+                {"participant": {"id": 3, "name": "Gamma"}}
+            ],
+            "matches": [
+                {"match": {"identifier": "B", "player1_id": 3, "player2_id": null, "suggested_play_order": 2}},
+                {"match": {"identifier": "A", "player1_id": 11, "player2_id": 2, "suggested_play_order": 1}}
+            ]
+        }}"#;
+        let matches = parse_challonge(text, &tracks()).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].id, "A");
+        assert_eq!(team(&matches[0], "Track1").as_deref(), Some("Alpha"));
+        assert_eq!(team(&matches[0], "Track2").as_deref(), Some("Beta"));
+        // 勝者が決まっていない枠は空
+        assert_eq!(matches[1].id, "B");
+        assert_eq!(team(&matches[1], "Track1").as_deref(), Some("Gamma"));
+        assert_eq!(team(&matches[1], "Track2"), None);
+        assert!(matches[1].speakers.is_empty());
+    }
+
+    #[test]
+    fn startgg_sets_and_pages_are_read() {
+        let text = r#"{"data": {"event": {"sets": {
+            "pageInfo": {"totalPages": 3},
+            "nodes": [
+                {"identifier": "A", "slots": [
+                    {"entrant": {"name": "Alpha"}},
+                    {"entrant": {"name": "Beta"}},
+                    {"entrant": {"name": "Extra"}}
+                ]},
+                {"identifier": "B", "slots": [{"entrant": null}, {"entrant": {"name": "Gamma"}}]}
+            ]
+        }}}}"#;
+        let (matches, total_pages) = parse_startgg(text, &tracks()).unwrap();
+        assert_eq!(total_pages, 3);
+        assert_eq!(matches[0].id, "A");
+        assert_eq!(matches[0].teams.len(), 2);
+        assert_eq!(team(&matches[0], "Track2").as_deref(), Some("Beta"));
+        assert_eq!(team(&matches[1], "Track1"), None);
+        assert_eq!(team(&matches[1], "Track2").as_deref(), Some("Gamma"));
+    }
+
+    #[test]
+    fn startgg_errors_are_reported() {
+        let text = r#"{"data": null, "errors": [{"message": "Invalid authentication token"}]}"#;
+        assert!(matches!(
+            parse_startgg(text, &tracks()),
+            Err(VcError::Schedule(message)) if message == "Invalid authentication token"
+        ));
+        let text = r#"{"data": {"event": null}}"#;
+        assert!(parse_startgg(text, &tracks()).is_err());
+    }
+}
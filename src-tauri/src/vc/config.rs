@@ -2,26 +2,269 @@ use std::{collections::HashMap, sync::Mutex};
 
 use confy::ConfyError;
 use serde::{Deserialize, Serialize};
-use serenity::all::{GuildId, UserId};
+use serenity::all::{ChannelId, GuildId, UserId};
+
+use super::{
+    bracket::BracketKeys,
+    discovery::DiscoveryRule,
+    error::VcError,
+    relay::BackpressurePolicy,
+    schedule::ScheduledMatch,
+    types::{PubIdentify, Route, SessionId, SubIdentify},
+    volume::TrackVolume,
+};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SpeakerConfig {
+    pub track: PubIdentify,
+    // 省略時はbot_poolから割り当てる
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<String>,
+    // 省略時はguild_idを使う
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guild_id: Option<GuildId>,
+    // このユーザーが別のVCに移ったらspeakerも移る
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<UserId>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ListenerConfig {
+    pub name: SubIdentify,
+    // 省略時はbot_poolから割り当てる
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<String>,
+    // 省略時はguild_idを使う
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guild_id: Option<GuildId>,
+}
+
+// 試合ごとのチャンネル選択．GUIから保存して呼び出す
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MatchPreset {
+    pub name: String,
+    #[serde(default)]
+    pub speakers: HashMap<PubIdentify, ChannelId>,
+    #[serde(default)]
+    pub listeners: HashMap<SubIdentify, ChannelId>,
+    // trackの表示名(チーム名など)
+    #[serde(default)]
+    pub labels: HashMap<PubIdentify, String>,
+    // 聞き取るtrack(含まれないtrackは止める)
+    #[serde(default)]
+    pub listening: Vec<PubIdentify>,
+}
+
+// 1試合分のrelay設定
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SessionConfig {
+    pub id: SessionId,
+    #[serde(default)]
+    pub speakers: Vec<SpeakerConfig>,
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    // 空の場合は全trackを全listenerにgain 1.0で流す
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    // trackによらないユーザーの音量
+    #[serde(default)]
+    pub user_volumes: HashMap<UserId, f32>,
+    // trackごとの既定の音量と，そのtrackでのユーザーの音量
+    #[serde(default)]
+    pub track_volumes: HashMap<PubIdentify, TrackVolume>,
+    // 画面で最後に合わせた音量(trackの既定より優先度は低い)
+    #[serde(default)]
+    pub last_volumes: HashMap<UserId, f32>,
+    // どこにも設定が無い場合の音量(省略時は1.0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_volume: Option<f32>,
+    // listenerの再生が遅れたときの方針
+    #[serde(default)]
+    pub backpressure: BackpressurePolicy,
+    #[serde(default)]
+    pub presets: Vec<MatchPreset>,
+    // 読み込んだ大会スケジュール(起動し直しても残す)
+    #[serde(default)]
+    pub schedule: Vec<ScheduledMatch>,
+    // 試合番号からチャンネルを選ぶルール
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discovery: Option<DiscoveryRule>,
+}
+
+impl SessionConfig {
+    pub fn routes(&self) -> Vec<Route> {
+        if !self.routes.is_empty() {
+            return self.routes.clone();
+        }
+        self.speakers
+            .iter()
+            .flat_map(|speaker| {
+                self.listeners.iter().map(|listener| Route {
+                    track: speaker.track.clone(),
+                    listener: listener.name.clone(),
+                    gain: 1.,
+                })
+            })
+            .collect()
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct MyConfig {
     pub guild_id: GuildId,
-    pub speaker1_api: String,
-    pub speaker2_api: String,
-    pub listener_api: String,
-    pub user_volumes: HashMap<UserId, f32>,
+    #[serde(default)]
+    pub sessions: Vec<SessionConfig>,
+    // apiを指定していないspeaker/listenerに割り当てるBotのToken
+    #[serde(default)]
+    pub bot_pool: Vec<String>,
+    // OBS用Overlayサーバーのポート
+    #[serde(default = "default_overlay_port")]
+    pub overlay_port: u16,
+    // start.gg/Challongeからスケジュールを読み込むときの認証情報
+    #[serde(default)]
+    pub bracket: BracketKeys,
+    // 旧形式(sessionsに移行して保存し直す)
+    #[serde(default, skip_serializing)]
+    speakers: Vec<SpeakerConfig>,
+    #[serde(default, skip_serializing)]
+    listeners: Vec<ListenerConfig>,
+    #[serde(default, skip_serializing)]
+    routes: Vec<Route>,
+    #[serde(default, skip_serializing)]
+    user_volumes: HashMap<UserId, f32>,
+    #[serde(default, skip_serializing)]
+    speaker1_api: Option<String>,
+    #[serde(default, skip_serializing)]
+    speaker2_api: Option<String>,
+    #[serde(default, skip_serializing)]
+    listener_api: Option<String>,
+}
+
+fn default_overlay_port() -> u16 {
+    8765
 }
 
 impl ::std::default::Default for MyConfig {
     fn default() -> Self {
         Self {
             guild_id: GuildId::new(1),
-            speaker1_api: "API_HERE".to_owned(),
-            speaker2_api: "API_HERE".to_owned(),
-            listener_api: "API_HERE".to_owned(),
+            sessions: vec![SessionConfig {
+                id: SessionId("main".to_owned()),
+                speakers: vec![
+                    SpeakerConfig {
+                        track: PubIdentify("Track1".to_owned()),
+                        api: Some("API_HERE".to_owned()),
+                        guild_id: None,
+                        anchor: None,
+                    },
+                    SpeakerConfig {
+                        track: PubIdentify("Track2".to_owned()),
+                        api: Some("API_HERE".to_owned()),
+                        guild_id: None,
+                        anchor: None,
+                    },
+                ],
+                listeners: vec![ListenerConfig {
+                    name: SubIdentify("Listener".to_owned()),
+                    api: Some("API_HERE".to_owned()),
+                    guild_id: None,
+                }],
+                routes: Vec::new(),
+                user_volumes: HashMap::new(),
+                track_volumes: HashMap::new(),
+                last_volumes: HashMap::new(),
+                default_volume: None,
+                backpressure: BackpressurePolicy::default(),
+                presets: Vec::new(),
+                schedule: Vec::new(),
+                discovery: None,
+            }],
+            bot_pool: Vec::new(),
+            overlay_port: default_overlay_port(),
+            bracket: BracketKeys::default(),
+            speakers: Vec::new(),
+            listeners: Vec::new(),
+            routes: Vec::new(),
             user_volumes: HashMap::new(),
+            speaker1_api: None,
+            speaker2_api: None,
+            listener_api: None,
+        }
+    }
+}
+
+impl MyConfig {
+    // 旧形式の設定を"main" sessionに移す
+    fn migrate_legacy(&mut self) -> bool {
+        if !self.sessions.is_empty() {
+            return false;
+        }
+        let mut speakers = std::mem::take(&mut self.speakers);
+        if speakers.is_empty() {
+            let legacy = [self.speaker1_api.take(), self.speaker2_api.take()];
+            speakers = legacy
+                .into_iter()
+                .enumerate()
+                .filter_map(|(i, api)| {
+                    api.map(|api| SpeakerConfig {
+                        track: PubIdentify(format!("Track{}", i + 1)),
+                        api: Some(api),
+                        guild_id: None,
+                        anchor: None,
+                    })
+                })
+                .collect();
         }
+        let mut listeners = std::mem::take(&mut self.listeners);
+        if listeners.is_empty() {
+            if let Some(api) = self.listener_api.take() {
+                listeners.push(ListenerConfig {
+                    name: SubIdentify("Listener".to_owned()),
+                    api: Some(api),
+                    guild_id: None,
+                });
+            }
+        }
+        if speakers.is_empty() && listeners.is_empty() {
+            return false;
+        }
+        self.sessions.push(SessionConfig {
+            id: SessionId("main".to_owned()),
+            speakers,
+            listeners,
+            routes: std::mem::take(&mut self.routes),
+            user_volumes: std::mem::take(&mut self.user_volumes),
+            track_volumes: HashMap::new(),
+            last_volumes: HashMap::new(),
+            default_volume: None,
+            backpressure: BackpressurePolicy::default(),
+            presets: Vec::new(),
+            schedule: Vec::new(),
+            discovery: None,
+        });
+        true
+    }
+    // sessionで直接指定されているTokenを除いたbot_pool
+    pub fn pooled_tokens(&self) -> Vec<String> {
+        let assigned: Vec<&String> = self
+            .sessions
+            .iter()
+            .flat_map(|session| {
+                let speakers = session.speakers.iter().filter_map(|s| s.api.as_ref());
+                let listeners = session.listeners.iter().filter_map(|l| l.api.as_ref());
+                speakers.chain(listeners)
+            })
+            .collect();
+        self.bot_pool
+            .iter()
+            .filter(|token| !assigned.contains(token))
+            .cloned()
+            .collect()
+    }
+    fn session_mut(&mut self, session_id: &SessionId) -> Option<&mut SessionConfig> {
+        self.sessions
+            .iter_mut()
+            .find(|session| session.id == *session_id)
     }
 }
 
@@ -32,20 +275,177 @@ pub struct ConfigManager {
     cfg: Mutex<MyConfig>,
 }
 impl ConfigManager {
-    pub fn new(path: String) -> Self {
-        ConfigManager {
-            path: path.clone(),
-            cfg: Mutex::new(confy::load_path::<MyConfig>(path).unwrap()),
+    // 書式の誤りなどで読み込めない場合はVcError::Config
+    pub fn new(path: String) -> Result<Self, VcError> {
+        let mut cfg = confy::load_path::<MyConfig>(&path)?;
+        for session in &cfg.sessions {
+            session.backpressure.validate(&session.id)?;
+            if let Some(discovery) = &session.discovery {
+                discovery.validate(&session.id)?;
+            }
         }
+        if cfg.migrate_legacy() {
+            if let Err(e) = confy::store_path(&path, cfg.clone()) {
+                log::error!("failed to store migrated config: {:?}", e);
+            }
+        }
+        Ok(ConfigManager {
+            path,
+            cfg: Mutex::new(cfg),
+        })
     }
     pub fn get_cfg(&self) -> MyConfig {
         let cfg = self.cfg.lock().unwrap();
         cfg.clone()
     }
-    pub fn update_volume(&self, user_id: UserId, volume: f32) -> Result<(), ConfyError> {
+    // Volumes::setと同じように，trackを指定した場合は(track, user)と最後に合わせた音量(track_volumes，last_volumes)を，
+    // 指定しない場合はuserの既定(user_volumes)を書き換える
+    pub fn update_volume(
+        &self,
+        session_id: &SessionId,
+        track: Option<&PubIdentify>,
+        user_id: UserId,
+        volume: f32,
+    ) -> Result<(), ConfyError> {
         let mut cfg = self.cfg.lock().unwrap();
-        cfg.user_volumes.insert(user_id, volume);
+        if let Some(session) = cfg.session_mut(session_id) {
+            match track {
+                Some(track) => {
+                    let track_volume = session.track_volumes.entry(track.clone()).or_default();
+                    track_volume.users.insert(user_id, volume);
+                    session.last_volumes.insert(user_id, volume);
+                }
+                None => {
+                    session.user_volumes.insert(user_id, volume);
+                }
+            }
+        }
         let cfg_cpy = cfg.clone();
         confy::store_path(&self.path, cfg_cpy)
     }
+    pub fn add_speaker(
+        &self,
+        session_id: &SessionId,
+        speaker: SpeakerConfig,
+    ) -> Result<(), ConfyError> {
+        let mut cfg = self.cfg.lock().unwrap();
+        if let Some(session) = cfg.session_mut(session_id) {
+            // routesを明示している場合は全listenerへのrouteを足す
+            if !session.routes.is_empty() {
+                let routes: Vec<Route> = session
+                    .listeners
+                    .iter()
+                    .map(|listener| Route {
+                        track: speaker.track.clone(),
+                        listener: listener.name.clone(),
+                        gain: 1.,
+                    })
+                    .collect();
+                session.routes.extend(routes);
+            }
+            session.speakers.push(speaker);
+        }
+        let cfg_cpy = cfg.clone();
+        confy::store_path(&self.path, cfg_cpy)
+    }
+    pub fn add_listener(
+        &self,
+        session_id: &SessionId,
+        listener: ListenerConfig,
+    ) -> Result<(), ConfyError> {
+        let mut cfg = self.cfg.lock().unwrap();
+        if let Some(session) = cfg.session_mut(session_id) {
+            if !session.routes.is_empty() {
+                let routes: Vec<Route> = session
+                    .speakers
+                    .iter()
+                    .map(|speaker| Route {
+                        track: speaker.track.clone(),
+                        listener: listener.name.clone(),
+                        gain: 1.,
+                    })
+                    .collect();
+                session.routes.extend(routes);
+            }
+            session.listeners.push(listener);
+        }
+        let cfg_cpy = cfg.clone();
+        confy::store_path(&self.path, cfg_cpy)
+    }
+    pub fn update_route(&self, session_id: &SessionId, route: Route) -> Result<(), ConfyError> {
+        let mut cfg = self.cfg.lock().unwrap();
+        if let Some(session) = cfg.session_mut(session_id) {
+            // 既定のroutesを書き出してから更新する
+            let mut routes = session.routes();
+            match routes
+                .iter_mut()
+                .find(|r| r.track == route.track && r.listener == route.listener)
+            {
+                Some(r) => r.gain = route.gain,
+                None => routes.push(route),
+            }
+            session.routes = routes;
+        }
+        let cfg_cpy = cfg.clone();
+        confy::store_path(&self.path, cfg_cpy)
+    }
+    // 同じ名前のpresetは上書きする
+    pub fn save_preset(
+        &self,
+        session_id: &SessionId,
+        preset: MatchPreset,
+    ) -> Result<(), ConfyError> {
+        let mut cfg = self.cfg.lock().unwrap();
+        if let Some(session) = cfg.session_mut(session_id) {
+            match session.presets.iter_mut().find(|p| p.name == preset.name) {
+                Some(p) => *p = preset,
+                None => session.presets.push(preset),
+            }
+        }
+        let cfg_cpy = cfg.clone();
+        confy::store_path(&self.path, cfg_cpy)
+    }
+    pub fn set_anchor(
+        &self,
+        session_id: &SessionId,
+        track: &PubIdentify,
+        anchor: Option<UserId>,
+    ) -> Result<(), ConfyError> {
+        let mut cfg = self.cfg.lock().unwrap();
+        if let Some(session) = cfg.session_mut(session_id) {
+            if let Some(speaker) = session.speakers.iter_mut().find(|s| s.track == *track) {
+                speaker.anchor = anchor;
+            }
+        }
+        let cfg_cpy = cfg.clone();
+        confy::store_path(&self.path, cfg_cpy)
+    }
+    pub fn set_schedule(
+        &self,
+        session_id: &SessionId,
+        schedule: Vec<ScheduledMatch>,
+    ) -> Result<(), ConfyError> {
+        let mut cfg = self.cfg.lock().unwrap();
+        if let Some(session) = cfg.session_mut(session_id) {
+            session.schedule = schedule;
+        }
+        let cfg_cpy = cfg.clone();
+        confy::store_path(&self.path, cfg_cpy)
+    }
+    // 消したらtrue
+    pub fn delete_preset(&self, session_id: &SessionId, name: &str) -> Result<bool, ConfyError> {
+        let mut cfg = self.cfg.lock().unwrap();
+        let session = match cfg.session_mut(session_id) {
+            Some(session) => session,
+            None => return Ok(false),
+        };
+        let len = session.presets.len();
+        session.presets.retain(|p| p.name != name);
+        if session.presets.len() == len {
+            return Ok(false);
+        }
+        let cfg_cpy = cfg.clone();
+        confy::store_path(&self.path, cfg_cpy)?;
+        Ok(true)
+    }
 }
@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use serde::Serialize;
+use serenity::all::{GuildId, Member, User, UserId};
+use tokio::sync::RwLock;
+
+use super::backend::VoiceBackend;
+
+// ニックネームやアイコンを変えた場合もこの時間で反映される
+const MEMBER_TTL: Duration = Duration::from_secs(10 * 60);
+// 取得に失敗したユーザーを取り直すまでの時間
+const FAILED_TTL: Duration = Duration::from_secs(30);
+
+// サーバーでの表示名とアイコン
+#[derive(Serialize, Clone, Debug)]
+pub struct MemberInfo {
+    pub user_id: UserId,
+    // ニックネーム > 表示名 > ユーザー名
+    pub display_name: String,
+    pub username: String,
+    pub nick: Option<String>,
+    pub global_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+impl MemberInfo {
+    pub fn from_member(member: &Member) -> Self {
+        MemberInfo {
+            user_id: member.user.id,
+            display_name: member.display_name().to_owned(),
+            username: member.user.name.clone(),
+            nick: member.nick.clone(),
+            global_name: member.user.global_name.clone(),
+            avatar_url: Some(member.face()),
+        }
+    }
+    // サーバーのメンバーでない場合
+    pub fn from_user(user: &User) -> Self {
+        MemberInfo {
+            user_id: user.id,
+            display_name: user.display_name().to_owned(),
+            username: user.name.clone(),
+            nick: None,
+            global_name: user.global_name.clone(),
+            avatar_url: Some(user.face()),
+        }
+    }
+    // 取得できなかった場合はidを名前にする
+    pub fn unknown(user_id: UserId) -> Self {
+        MemberInfo {
+            user_id,
+            display_name: user_id.to_string(),
+            username: user_id.to_string(),
+            nick: None,
+            global_name: None,
+            avatar_url: None,
+        }
+    }
+}
+
+// ユーザーの表示名をTTL付きでキャッシュする(発話のたびにREST APIを叩かない)
+pub struct MemberDirectory {
+    backend: Arc<dyn VoiceBackend>,
+    // 期限と一緒に持つ(取得に失敗した場合も短い間は覚えておく)
+    members: RwLock<HashMap<(GuildId, UserId), (MemberInfo, Instant)>>,
+}
+
+impl MemberDirectory {
+    pub fn new(backend: Arc<dyn VoiceBackend>) -> Self {
+        MemberDirectory {
+            backend,
+            members: RwLock::new(HashMap::new()),
+        }
+    }
+    // gatewayのcacheなどで分かっている情報を入れておく
+    pub async fn insert(&self, guild_id: GuildId, member: MemberInfo) {
+        self.insert_for(guild_id, member, MEMBER_TTL).await;
+    }
+    async fn insert_for(&self, guild_id: GuildId, member: MemberInfo, ttl: Duration) {
+        let mut members = self.members.write().await;
+        members.insert((guild_id, member.user_id), (member, Instant::now() + ttl));
+    }
+    // cacheの情報(無ければidを名前にする)と，REST APIで取り直す必要があるか
+    // 待たないので，取り直す場合はfetchを別taskで呼ぶ
+    pub async fn lookup(&self, guild_id: GuildId, user_id: UserId) -> (MemberInfo, bool) {
+        let members = self.members.read().await;
+        match members.get(&(guild_id, user_id)) {
+            Some((member, expires_at)) => (member.clone(), *expires_at <= Instant::now()),
+            None => (MemberInfo::unknown(user_id), true),
+        }
+    }
+    pub async fn fetch(&self, guild_id: GuildId, user_id: UserId) -> MemberInfo {
+        match self.backend.member(guild_id, user_id).await {
+            Ok(member) => {
+                self.insert(guild_id, member.clone()).await;
+                member
+            }
+            Err(e) => {
+                warn!("failed to get member {}: {}", user_id, e);
+                // 失敗した場合も，しばらくは取り直さない(REST APIを叩き続けない)
+                let member = MemberInfo::unknown(user_id);
+                self.insert_for(guild_id, member.clone(), FAILED_TTL).await;
+                member
+            }
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, LazyLock,
+        Arc, Mutex, RwLock,
     },
 };
 
@@ -10,9 +10,13 @@ use dashmap::DashMap;
 use log::{debug, error, info};
 
 use serenity::{
-    all::{ClientBuilder, Context, GuildId},
+    all::{
+        ChannelId, ClientBuilder, Context, GuildChannel, GuildId, Permissions, UserId as BotUserId,
+        VoiceState,
+    },
     async_trait,
     client::EventHandler,
+    gateway::ShardManager,
     model::gateway::Ready,
     prelude::GatewayIntents,
     Client,
@@ -27,36 +31,182 @@ use songbird::{
     Call, Config, CoreEvent, Event, EventContext, EventHandler as VoiceEventHandler, SerenityInit,
     Songbird,
 };
-use tokio::sync::RwLock;
 
 use crate::vc::types::{
-    JoinInfo, SendEnum, UserInfo, VoiceManagerSenderType, VoiceType, VoiceUserEvent,
+    AnchorMoved, AnchorMovedSenderType, CtxType, JoinInfo, SendEnum, SpeakingInfo, UserInfo,
+    VoiceManagerSenderType, VoiceType, VoiceUserEvent,
+};
+
+use super::{
+    backend::VoiceSource,
+    directory::MemberInfo,
+    dis_sub::{check_permissions, fetch_channels, fetch_voice_channels},
+    error::VcError,
+    join_session::TaskScope,
+    reconnect::{Reconnector, ReconnectorSlot},
+    relay::RelayMetrics,
+    rest::Rest,
+    status::{BotState, BotStatus, StatusReporter},
+    types::{GuildChannelsType, PubIdentify},
 };
 
-use super::types::PubIdentify;
+// Join中のチャンネルにいるユーザー
+// 話していないユーザーもフロントエンドに出すため，cacheのvoice_statesから通知する
+#[derive(Clone, Default)]
+pub(super) struct Roster {
+    tx: Arc<Mutex<Option<VoiceManagerSenderType>>>,
+    // 通知済みのユーザー
+    members: Arc<Mutex<HashSet<BotUserId>>>,
+}
+
+impl Roster {
+    pub(super) fn start(&self, tx: VoiceManagerSenderType) {
+        *self.tx.lock().unwrap() = Some(tx);
+        self.members.lock().unwrap().clear();
+    }
+    pub(super) fn clear(&self) {
+        *self.tx.lock().unwrap() = None;
+        self.members.lock().unwrap().clear();
+    }
+    // channel_idにいるユーザー(cacheのvoice_states)と通知済みのユーザーの差分をVoiceManagerに送る
+    async fn sync(
+        &self,
+        ctx: &Context,
+        identify: &PubIdentify,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) {
+        let bot_id = ctx.cache.current_user().id;
+        let present: HashMap<BotUserId, Option<MemberInfo>> = match ctx.cache.guild(guild_id) {
+            Some(guild) => guild
+                .voice_states
+                .values()
+                .filter(|state| state.channel_id == Some(channel_id) && state.user_id != bot_id)
+                // 他の中継Botなどは出さない
+                .filter(|state| !state.member.as_ref().is_some_and(|m| m.user.bot))
+                .map(|state| {
+                    let member = state
+                        .member
+                        .as_ref()
+                        .or_else(|| guild.members.get(&state.user_id))
+                        .map(MemberInfo::from_member);
+                    (state.user_id, member)
+                })
+                .collect(),
+            None => return,
+        };
+        self.sync_present(identify, guild_id, present).await;
+    }
+    // presentと通知済みのユーザーの差分を送る
+    pub(super) async fn sync_present(
+        &self,
+        identify: &PubIdentify,
+        guild_id: GuildId,
+        present: HashMap<BotUserId, Option<MemberInfo>>,
+    ) {
+        let tx = match self.tx.lock().unwrap().clone() {
+            Some(tx) => tx,
+            None => return,
+        };
+        let (joined, left) = {
+            let mut members = self.members.lock().unwrap();
+            let joined: Vec<(BotUserId, Option<MemberInfo>)> = present
+                .iter()
+                .filter(|(user_id, _)| !members.contains(*user_id))
+                .map(|(user_id, member)| (*user_id, member.clone()))
+                .collect();
+            let left: Vec<BotUserId> = members
+                .iter()
+                .filter(|user_id| !present.contains_key(*user_id))
+                .copied()
+                .collect();
+            *members = present.into_keys().collect();
+            (joined, left)
+        };
+        let events = joined
+            .into_iter()
+            .map(|(user_id, member)| (user_id, member, VoiceUserEvent::Join))
+            .chain(
+                left.into_iter()
+                    .map(|user_id| (user_id, None, VoiceUserEvent::Leave)),
+            );
+        for (user_id, member, event) in events {
+            let user_data = UserInfo {
+                user_id: UserId(user_id.get()),
+                event,
+                identify: identify.clone(),
+                guild_id,
+                member,
+            };
+            if let Err(e) = tx.send(SendEnum::UserData(user_data)).await {
+                error!("failed to send user data: {:?}", e);
+            }
+        }
+    }
+}
+
+// ctxはPubごとに持つ(再接続でreadyが再度来たら差し替える)
+struct Handler {
+    ctx: CtxType,
+    reconnector: ReconnectorSlot,
+    status: StatusReporter,
+    identify: PubIdentify,
+    guild_id: GuildId,
+    anchor: Arc<RwLock<Option<BotUserId>>>,
+    anchor_tx: AnchorMovedSenderType,
+    roster: Roster,
+}
 
-// 複数Speakerに対応するためのHashMap
-// KeyはDiscordのusername
-// ! idにしたほうが確実
-static CTXS: LazyLock<Arc<RwLock<HashMap<String, serenity::prelude::Context>>>> =
-    LazyLock::new(|| Arc::new(RwLock::new(HashMap::new())));
-static ISLISTENING: LazyLock<Arc<RwLock<HashMap<String, bool>>>> =
-    LazyLock::new(|| Arc::new(RwLock::new(HashMap::new())));
-struct Handler;
+impl Handler {
+    // Join中のチャンネルへの出入りを反映する
+    async fn sync_roster(&self, ctx: &Context, state: &VoiceState) {
+        if state.guild_id != Some(self.guild_id) {
+            return;
+        }
+        let channel_id = match self.reconnector.read().await.as_ref() {
+            Some(reconnector) => reconnector.channel_id(),
+            None => return,
+        };
+        self.roster
+            .sync(ctx, &self.identify, self.guild_id, channel_id)
+            .await;
+    }
+    // anchorのユーザーがJoin中と別のVCに移ったら知らせる(VCから抜けただけなら残る)
+    async fn follow_anchor(&self, state: &VoiceState) {
+        let anchor = *self.anchor.read().unwrap();
+        if anchor != Some(state.user_id) || state.guild_id != Some(self.guild_id) {
+            return;
+        }
+        let channel_id = match state.channel_id {
+            Some(channel_id) => channel_id,
+            None => return,
+        };
+        let current = match self.reconnector.read().await.as_ref() {
+            Some(reconnector) => reconnector.channel_id(),
+            None => return,
+        };
+        if current != channel_id {
+            info!("{}: anchor moved to {}", self.identify, channel_id);
+            let _ = self.anchor_tx.send(AnchorMoved {
+                track: self.identify.clone(),
+                channel_id,
+            });
+        }
+    }
+}
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, ctx: serenity::prelude::Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
-        let key = &ready.user.name;
-        {
-            let mut ctxs = CTXS.write().await;
-            ctxs.insert(key.to_owned(), ctx);
-        }
-        {
-            let mut is_listening = ISLISTENING.write().await;
-            is_listening.insert(key.to_owned(), false);
-        }
+        let mut ctx_writer = self.ctx.write().await;
+        *ctx_writer = Some(ctx);
+        self.status.set_ready();
+    }
+    async fn voice_state_update(&self, ctx: Context, _old: Option<VoiceState>, new: VoiceState) {
+        Reconnector::on_voice_state_update(&self.reconnector, &ctx, &new).await;
+        self.follow_anchor(&new).await;
+        self.sync_roster(&ctx, &new).await;
     }
 }
 
@@ -65,26 +215,53 @@ struct Receiver {
     inner: Arc<InnerReceiver>,
     tx: VoiceManagerSenderType,
     identify: PubIdentify,
-    user_name: String,
+    guild_id: GuildId,
+    is_listening: Arc<AtomicBool>,
+    metrics: Arc<RelayMetrics>,
 }
 
 struct InnerReceiver {
     last_tick_was_empty: AtomicBool,
     known_ssrcs: DashMap<u32, UserId>,
+    // ssrcごとの直前の発話状態
+    speaking_ssrcs: DashMap<u32, bool>,
 }
 
 impl Receiver {
-    pub fn new(tx: VoiceManagerSenderType, identify: PubIdentify, user_name: String) -> Self {
+    pub fn new(
+        tx: VoiceManagerSenderType,
+        identify: PubIdentify,
+        guild_id: GuildId,
+        is_listening: Arc<AtomicBool>,
+        metrics: Arc<RelayMetrics>,
+    ) -> Self {
         // You can manage state here, such as a buffer of audio packet bytes so
         // you can later store them in intervals.
         Self {
             inner: Arc::new(InnerReceiver {
                 last_tick_was_empty: AtomicBool::default(),
                 known_ssrcs: DashMap::new(),
+                speaking_ssrcs: DashMap::new(),
             }),
             tx,
             identify,
-            user_name,
+            guild_id,
+            is_listening,
+            metrics,
+        }
+    }
+    // 発話状態が変化したときだけVoiceManagerに通知する
+    async fn update_speaking(&self, ssrc: u32, speaking: bool) {
+        let user_id = match self.inner.known_ssrcs.get(&ssrc) {
+            Some(id) => *id,
+            None => return,
+        };
+        if self.inner.speaking_ssrcs.insert(ssrc, speaking) == Some(speaking) {
+            return;
+        }
+        let speaking_data = SpeakingInfo { user_id, speaking };
+        if let Err(e) = self.tx.send(SendEnum::SpeakingState(speaking_data)).await {
+            error!("failed to send speaking state: {:?}", e);
         }
     }
 }
@@ -122,12 +299,22 @@ impl VoiceEventHandler for Receiver {
                     let user_data = UserInfo {
                         user_id: user.to_owned(),
                         event: VoiceUserEvent::Join,
-                        identify: self.identify,
+                        identify: self.identify.clone(),
+                        guild_id: self.guild_id,
+                        member: None,
                     };
-                    self.tx.send(SendEnum::UserData(user_data)).await.unwrap();
+                    if let Err(e) = self.tx.send(SendEnum::UserData(user_data)).await {
+                        error!("failed to send user data: {:?}", e);
+                    }
                 }
             }
             Ctx::VoiceTick(tick) => {
+                let speaking_ssrcs = tick.speaking.keys().map(|ssrc| (*ssrc, true));
+                let silent_ssrcs = tick.silent.iter().map(|ssrc| (*ssrc, false));
+                for (ssrc, is_speaking) in speaking_ssrcs.chain(silent_ssrcs) {
+                    self.update_speaking(ssrc, is_speaking).await;
+                }
+
                 let speaking = tick.speaking.len();
                 let total_participants = speaking + tick.silent.len();
                 let last_tick_was_empty = self.inner.last_tick_was_empty.load(Ordering::SeqCst);
@@ -164,20 +351,13 @@ impl VoiceEventHandler for Receiver {
                                 &decoded_voice[..voice_len.min(5)]
                             );
                             let pcm = decoded_voice.to_vec();
-                            let send_data = VoiceType::new(user_id, pcm);
-                            let is_listening = {
-                                let map = ISLISTENING.read().await;
-                                let is_listening = map.get(&self.user_name);
-                                match is_listening {
-                                    None => false,
-                                    Some(l) => *l,
+                            let send_data = VoiceType::new(user_id, self.identify.clone(), pcm);
+                            if self.is_listening.load(Ordering::SeqCst) {
+                                // VoiceManagerが詰まっていても受信を止めない(溢れた音声は捨てる)
+                                if let Err(e) = self.tx.try_send(SendEnum::VoiceData(send_data)) {
+                                    self.metrics.count_dropped(1);
+                                    debug!("dropped voice data: {:?}", e);
                                 }
-                            };
-                            if is_listening {
-                                self.tx
-                                    .send(SendEnum::VoiceData(send_data))
-                                    .await
-                                    .expect("tx send failed");
                             }
                             if let Some(packet) = &data.packet {
                                 let rtp = packet.rtp();
@@ -231,9 +411,13 @@ impl VoiceEventHandler for Receiver {
                 let user_data = UserInfo {
                     user_id: user_id.to_owned(),
                     event: VoiceUserEvent::Leave,
-                    identify: self.identify,
+                    identify: self.identify.clone(),
+                    guild_id: self.guild_id,
+                    member: None,
                 };
-                self.tx.send(SendEnum::UserData(user_data)).await.unwrap();
+                if let Err(e) = self.tx.send(SendEnum::UserData(user_data)).await {
+                    error!("failed to send user data: {:?}", e);
+                }
                 debug!("Client disconnected: user {:?}", user_id);
             }
             _ => {
@@ -247,74 +431,264 @@ impl VoiceEventHandler for Receiver {
 }
 
 pub struct Pub {
-    user_name: String,
+    user_id: Option<BotUserId>,
     identify: PubIdentify,
+    guild_id: GuildId,
+    ctx: CtxType,
+    is_listening: Arc<AtomicBool>,
+    reconnector: ReconnectorSlot,
+    status: StatusReporter,
+    // 終了時にgatewayを閉じる
+    shard_manager: Option<Arc<ShardManager>>,
+    // 追従するユーザー(チームのキャプテンなど)
+    anchor: Arc<RwLock<Option<BotUserId>>>,
+    anchor_tx: AnchorMovedSenderType,
+    roster: Roster,
+    rest: Option<Rest>,
 }
 
 impl Pub {
-    pub fn new(identify: PubIdentify) -> Self {
+    pub fn new(
+        identify: PubIdentify,
+        guild_id: GuildId,
+        status: StatusReporter,
+        anchor_tx: AnchorMovedSenderType,
+    ) -> Self {
         Pub {
-            user_name: "".to_string(),
+            user_id: None,
             identify,
+            guild_id,
+            ctx: CtxType::default(),
+            is_listening: Arc::new(AtomicBool::new(false)),
+            reconnector: ReconnectorSlot::default(),
+            status,
+            shard_manager: None,
+            anchor: Arc::new(RwLock::new(None)),
+            anchor_tx,
+            roster: Roster::default(),
+            rest: None,
         }
     }
-    pub async fn create_client(&mut self, token: &str) -> Result<Client, serenity::Error> {
+    pub async fn create_client(&mut self, token: &str) -> Result<Client, VcError> {
         let intents = GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT;
         let songbird_config = Config::default().decode_mode(DecodeMode::Decode);
 
         let client = ClientBuilder::new(token, intents)
-            .event_handler(Handler)
+            .event_handler(Handler {
+                ctx: self.ctx.clone(),
+                reconnector: self.reconnector.clone(),
+                status: self.status.clone(),
+                identify: self.identify.clone(),
+                guild_id: self.guild_id,
+                anchor: self.anchor.clone(),
+                anchor_tx: self.anchor_tx.clone(),
+                roster: self.roster.clone(),
+            })
             .register_songbird_from_config(songbird_config)
             .await?;
-        let user_name = client.http.get_current_user().await?.name.clone();
-        self.user_name = user_name;
+        let rest = Rest::new(client.http.clone());
+        let user = rest
+            .call("get_current_user", |http| async move {
+                http.get_current_user().await
+            })
+            .await?;
+        self.user_id = Some(user.id);
+        self.shard_manager = Some(client.shard_manager.clone());
+        self.rest = Some(rest);
         Ok(client)
     }
-    pub async fn join(&self, join_info: JoinInfo, tx: VoiceManagerSenderType) {
+    async fn get_ctx(&self) -> Option<Context> {
+        self.ctx.read().await.clone()
+    }
+    pub(crate) fn rest(&self) -> Option<Rest> {
+        self.rest.clone()
+    }
+    // readyが来るまではREST APIも使わない
+    async fn get_rest(&self) -> Result<&Rest, VcError> {
+        match (self.get_ctx().await, self.rest.as_ref()) {
+            (Some(_), Some(rest)) => Ok(rest),
+            _ => Err(VcError::BotNotReady(self.identify.to_string())),
+        }
+    }
+    async fn get_manager(&self) -> Option<Arc<Songbird>> {
+        let ctx = self.get_ctx().await;
+        let ctx = match ctx {
+            None => {
+                error!("ctx None");
+                return None;
+            }
+            Some(ctx) => ctx,
+        };
+
+        songbird::get(&ctx).await
+    }
+    fn add_handler_event(handler: &mut Call, evt_receiver: Receiver) {
+        handler.add_global_event(CoreEvent::SpeakingStateUpdate.into(), evt_receiver.clone());
+        handler.add_global_event(CoreEvent::RtpPacket.into(), evt_receiver.clone());
+        handler.add_global_event(CoreEvent::RtcpPacket.into(), evt_receiver.clone());
+        handler.add_global_event(CoreEvent::ClientDisconnect.into(), evt_receiver.clone());
+        handler.add_global_event(CoreEvent::VoiceTick.into(), evt_receiver);
+    }
+    async fn _join_vc(&self, manager: Arc<Songbird>, join_info: JoinInfo) -> Result<(), VcError> {
+        if let Err(e) = manager.join(join_info.guild_id, join_info.channel_id).await {
+            // Although we failed to join, we need to clear out existing event handlers on the call.
+            _ = manager.remove(join_info.guild_id).await;
+            error!("failed to join vc:{:?}", e);
+            self.status.set(BotState::Failed);
+            return Err(VcError::Voice {
+                name: self.identify.to_string(),
+                reason: e.to_string(),
+            });
+        }
+        self.status.set(BotState::InChannel);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VoiceSource for Pub {
+    fn identify(&self) -> &PubIdentify {
+        &self.identify
+    }
+    fn guild_id(&self) -> GuildId {
+        self.guild_id
+    }
+    fn user_id(&self) -> Option<BotUserId> {
+        self.user_id
+    }
+    fn status(&self) -> BotStatus {
+        self.status.get()
+    }
+    async fn get_voice_channels(&self, guild_id: GuildId) -> Result<Vec<GuildChannel>, VcError> {
+        fetch_voice_channels(self.get_rest().await?, guild_id).await
+    }
+    async fn get_channels(&self, guild_id: GuildId) -> Result<GuildChannelsType, VcError> {
+        fetch_channels(self.get_rest().await?, guild_id).await
+    }
+    async fn join(
+        &self,
+        join_info: JoinInfo,
+        tx: VoiceManagerSenderType,
+        metrics: Arc<RelayMetrics>,
+        tasks: &TaskScope,
+    ) -> Result<(), VcError> {
         info!("info:{:?}", join_info);
+        let ctx = self
+            .get_ctx()
+            .await
+            .ok_or_else(|| VcError::BotNotReady(self.identify.to_string()))?;
+        check_permissions(&ctx, &self.identify.0, join_info, Permissions::CONNECT)?;
         let manager = self.get_manager().await;
         let manager = match manager {
             None => {
                 error!("songbird get error");
-                return;
+                return Err(VcError::BotNotReady(self.identify.to_string()));
             }
             Some(manager) => manager,
         };
+        self.roster.start(tx.clone());
+        let evt_receiver = Receiver::new(
+            tx,
+            self.identify.clone(),
+            self.guild_id,
+            self.is_listening.clone(),
+            metrics,
+        );
+        let reconnector = Reconnector::new(
+            self.identify.to_string(),
+            manager.clone(),
+            join_info,
+            Arc::new(move |handler: &mut Call| {
+                Pub::add_handler_event(handler, evt_receiver.clone());
+            }),
+            self.status.clone(),
+            tasks.clone(),
+        );
         {
             let handler_lock = manager.clone().get_or_insert(join_info.guild_id);
             let mut handler = handler_lock.lock().await;
-            self.add_handler_event(&mut handler, tx.clone()).await;
+            reconnector.setup(&mut handler);
         }
-        self._join_vc(manager, join_info).await;
+        if let Some(old) = self.reconnector.write().await.replace(reconnector) {
+            old.stop();
+        }
+        // 受信のhandlerはJoin前に登録しておく(入った直後の発話を取りこぼさない)
+        // 失敗した場合は，入れなかったチャンネルに再接続しないように止める
+        if let Err(e) = self._join_vc(manager, join_info).await {
+            if let Some(reconnector) = self.reconnector.write().await.take() {
+                reconnector.stop();
+            }
+            self.roster.clear();
+            return Err(e);
+        }
+        // 話していないユーザーも含めてチャンネルにいるユーザーを通知する
+        self.roster
+            .sync(
+                &ctx,
+                &self.identify,
+                join_info.guild_id,
+                join_info.channel_id,
+            )
+            .await;
+        Ok(())
     }
-    pub async fn set_is_listening(&self, is_listening: bool) {
-        let mut is_listening_writer = ISLISTENING.write().await;
-        is_listening_writer.insert(self.user_name.clone(), is_listening);
+    fn set_is_listening(&self, is_listening: bool) {
+        self.is_listening.store(is_listening, Ordering::SeqCst);
     }
-    async fn get_ctx(&self) -> Option<Context> {
-        let ctx_hash_map = CTXS.read().await;
-        debug!("ctx key:{}", self.user_name);
-        let ctx = ctx_hash_map.get(&self.user_name);
-        // ctx.map(|ctx| ctx.cloned())
-        ctx.cloned()
+    fn is_listening(&self) -> bool {
+        self.is_listening.load(Ordering::SeqCst)
     }
-    async fn get_manager(&self) -> Option<Arc<Songbird>> {
-        let ctx = self.get_ctx().await;
-        let ctx = match ctx {
-            None => {
-                error!("ctx None");
-                return None;
-            }
-            Some(ctx) => ctx,
+    fn set_anchor(&self, anchor: Option<BotUserId>) {
+        *self.anchor.write().unwrap() = anchor;
+    }
+    fn anchor(&self) -> Option<BotUserId> {
+        *self.anchor.read().unwrap()
+    }
+    async fn switch_channel(&self, channel_id: ChannelId) -> Result<(), VcError> {
+        let ctx = self
+            .get_ctx()
+            .await
+            .ok_or_else(|| VcError::BotNotReady(self.identify.to_string()))?;
+        let join_info = JoinInfo {
+            guild_id: self.guild_id,
+            channel_id,
         };
-
-        songbird::get(&ctx).await
+        check_permissions(&ctx, &self.identify.0, join_info, Permissions::CONNECT)?;
+        match Reconnector::switch_channel(&self.reconnector, channel_id).await {
+            Some(Ok(())) => {
+                // 前のチャンネルのユーザーは抜けたことにする
+                self.roster
+                    .sync(&ctx, &self.identify, self.guild_id, channel_id)
+                    .await;
+                Ok(())
+            }
+            Some(Err(e)) => Err(VcError::Voice {
+                name: self.identify.to_string(),
+                reason: e.to_string(),
+            }),
+            None => Err(VcError::NotInVc(self.identify.to_string())),
+        }
+    }
+    async fn shutdown(&self) {
+        self.status.set(BotState::Stopped);
+        if let Some(shard_manager) = &self.shard_manager {
+            shard_manager.shutdown_all().await;
+        }
     }
-    pub async fn leave(&self, guild_id: GuildId) -> Result<(), String> {
+    // readyイベントを受け取ったか
+    async fn is_ready(&self) -> bool {
+        self.get_ctx().await.is_some()
+    }
+    async fn leave(&self, guild_id: GuildId) -> Result<(), VcError> {
+        // 意図した退出なので再接続しない(gatewayが止まっていても止める)
+        if let Some(reconnector) = self.reconnector.write().await.take() {
+            reconnector.stop();
+        }
+        self.roster.clear();
         let manager = self.get_manager().await;
         let manager = match manager {
             None => {
-                return Err("songbird get error".to_owned());
+                return Err(VcError::BotNotReady(self.identify.to_string()));
             }
             Some(manager) => manager,
         };
@@ -326,26 +700,15 @@ impl Pub {
                     handler.remove_all_global_events();
                 }
                 if let Err(e) = manager.remove(guild_id).await {
-                    return Err(e.to_string());
+                    return Err(VcError::Voice {
+                        name: self.identify.to_string(),
+                        reason: e.to_string(),
+                    });
                 }
             }
-            None => return Err("Not in VC".to_owned()),
+            None => return Err(VcError::NotInVc(self.identify.to_string())),
         }
+        self.status.set(BotState::Ready);
         Ok(())
     }
-    async fn add_handler_event(&self, handler: &mut Call, tx: VoiceManagerSenderType) {
-        let evt_receiver = Receiver::new(tx.clone(), self.identify, self.user_name.clone());
-        handler.add_global_event(CoreEvent::SpeakingStateUpdate.into(), evt_receiver.clone());
-        handler.add_global_event(CoreEvent::RtpPacket.into(), evt_receiver.clone());
-        handler.add_global_event(CoreEvent::RtcpPacket.into(), evt_receiver.clone());
-        handler.add_global_event(CoreEvent::ClientDisconnect.into(), evt_receiver.clone());
-        handler.add_global_event(CoreEvent::VoiceTick.into(), evt_receiver);
-    }
-    async fn _join_vc(&self, manager: Arc<Songbird>, join_info: JoinInfo) {
-        if let Err(e) = manager.join(join_info.guild_id, join_info.channel_id).await {
-            // Although we failed to join, we need to clear out existing event handlers on the call.
-            _ = manager.remove(join_info.guild_id).await;
-            error!("failed to join vc:{:?}", e);
-        }
-    }
 }
@@ -1,13 +1,19 @@
 use log::{error, info};
 use serenity::{
-    all::{EventHandler, GatewayIntents, GuildChannel, GuildId, Ready},
-    async_trait, Client,
+    all::{
+        ChannelId, ChannelType, Context, EventHandler, GatewayIntents, GuildChannel, GuildId,
+        Permissions, Ready, UserId, VoiceState,
+    },
+    async_trait,
+    gateway::ShardManager,
+    Client,
 };
 use songbird::{
     input::{codecs::RawReader, Input, RawAdapter},
-    Config, SerenityInit, Songbird,
+    Call, Config, SerenityInit, Songbird,
 };
 use std::{
+    collections::HashMap,
     io::Cursor,
     sync::{Arc, OnceLock},
 };
@@ -15,131 +21,178 @@ use symphonia::{
     core::{codecs::CodecRegistry, probe::Probe},
     default::{codecs::PcmDecoder, register_enabled_codecs, register_enabled_formats},
 };
-use tokio::sync::RwLock;
 
 use crate::vc::types::JoinInfo;
 
-use super::types::VoiceReceiverType;
+use super::{
+    backend::VoiceSink,
+    error::VcError,
+    join_session::TaskScope,
+    reconnect::{Reconnector, ReconnectorSlot},
+    rest::Rest,
+    status::{BotState, BotStatus, StatusReporter},
+    types::{CtxType, GuildChannelsType, SubIdentify, VoiceReceiverType},
+};
 
 static CODEC_REGISTRY: OnceLock<CodecRegistry> = OnceLock::new();
 static PROBE: OnceLock<Probe> = OnceLock::new();
-static CTX: OnceLock<Arc<RwLock<serenity::prelude::Context>>> = OnceLock::new();
 
-pub struct Sub {}
+pub struct Sub {
+    user_id: Option<UserId>,
+    identify: SubIdentify,
+    guild_id: GuildId,
+    ctx: CtxType,
+    reconnector: ReconnectorSlot,
+    status: StatusReporter,
+    // 終了時にgatewayを閉じる
+    shard_manager: Option<Arc<ShardManager>>,
+    rest: Option<Rest>,
+}
 
-struct Handler;
+// ctxはSubごとに持つ(再接続でreadyが再度来たら差し替える)
+struct Handler {
+    ctx: CtxType,
+    reconnector: ReconnectorSlot,
+    status: StatusReporter,
+}
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, ctx: serenity::prelude::Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
-        CTX.set(Arc::new(RwLock::new(ctx))).unwrap();
+        let mut ctx_writer = self.ctx.write().await;
+        *ctx_writer = Some(ctx);
+        self.status.set_ready();
+    }
+    async fn voice_state_update(&self, ctx: Context, _old: Option<VoiceState>, new: VoiceState) {
+        Reconnector::on_voice_state_update(&self.reconnector, &ctx, &new).await;
+    }
+}
+
+// PubとSubで共通のVC一覧取得
+pub(crate) async fn fetch_voice_channels(
+    rest: &Rest,
+    guild_id: GuildId,
+) -> Result<Vec<GuildChannel>, VcError> {
+    let (voice_channels, _) = fetch_channels(rest, guild_id).await?;
+    Ok(voice_channels)
+}
+
+// VCとカテゴリのidと名前(チャンネルの自動選択用)を1回の取得で作る
+pub(crate) async fn fetch_channels(
+    rest: &Rest,
+    guild_id: GuildId,
+) -> Result<GuildChannelsType, VcError> {
+    let channels = rest
+        .call("get_channels", |http| async move {
+            guild_id.channels(&http).await
+        })
+        .await?;
+    let mut voice_channels = Vec::new();
+    let mut categories = HashMap::new();
+    for channel in channels.into_values() {
+        if channel.kind == ChannelType::Category {
+            categories.insert(channel.id, channel.name);
+        } else if channel.bitrate.is_some() {
+            voice_channels.push(channel);
+        }
     }
+    Ok((voice_channels, categories))
+}
+
+// Joinする前にBotの権限を確認する(cacheに無い場合はJoinに任せる)
+pub(crate) fn check_permissions(
+    ctx: &Context,
+    name: &str,
+    join_info: JoinInfo,
+    required: Permissions,
+) -> Result<(), VcError> {
+    let bot_id = ctx.cache.current_user().id;
+    let guild = match ctx.cache.guild(join_info.guild_id) {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let (channel, member) = match (
+        guild.channels.get(&join_info.channel_id),
+        guild.members.get(&bot_id),
+    ) {
+        (Some(channel), Some(member)) => (channel, member),
+        _ => return Ok(()),
+    };
+    let missing = required - guild.user_permissions_in(channel, member);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(VcError::MissingPermission {
+        name: name.to_owned(),
+        channel_id: join_info.channel_id,
+        permission: missing.get_permission_names().join(", "),
+    })
 }
 
 impl Sub {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(identify: SubIdentify, guild_id: GuildId, status: StatusReporter) -> Self {
+        Self {
+            user_id: None,
+            identify,
+            guild_id,
+            ctx: CtxType::default(),
+            reconnector: ReconnectorSlot::default(),
+            status,
+            shard_manager: None,
+            rest: None,
+        }
     }
-    pub async fn create_client(&self, token: &str) -> Result<Client, serenity::Error> {
+    pub async fn create_client(&mut self, token: &str) -> Result<Client, VcError> {
         let intents = GatewayIntents::non_privileged()
             | GatewayIntents::MESSAGE_CONTENT
             // Channelsに必要
             | GatewayIntents::GUILDS;
 
-        Client::builder(token, intents)
-            .event_handler(Handler)
+        let client = Client::builder(token, intents)
+            .event_handler(Handler {
+                ctx: self.ctx.clone(),
+                reconnector: self.reconnector.clone(),
+                status: self.status.clone(),
+            })
             .register_songbird()
-            .await
+            .await?;
+        let rest = Rest::new(client.http.clone());
+        let user = rest
+            .call("get_current_user", |http| async move {
+                http.get_current_user().await
+            })
+            .await?;
+        self.user_id = Some(user.id);
+        self.shard_manager = Some(client.shard_manager.clone());
+        self.rest = Some(rest);
+        Ok(client)
     }
-    pub async fn join(&self, join_info: JoinInfo, mut rx: VoiceReceiverType) {
-        let ctx = CTX.get();
-        let ctx_lock = match ctx {
-            None => {
-                error!("ctx None");
-                return;
-            }
-            Some(ctx) => ctx.clone(),
-        };
-        let ctx = ctx_lock.read().await;
-        let manager = songbird::get(&ctx).await;
-        let manager = match manager {
-            None => {
-                error!("songbird get error");
-                return;
-            }
-            Some(manager) => manager,
-        };
-        if let Ok(handler_lock) = manager.join(join_info.guild_id, join_info.channel_id).await {
-            {
-                let mut handler = handler_lock.lock().await;
-                let config = self.create_config();
-                handler.set_config(config);
-            }
-            let handler_lock = handler_lock.clone();
-            tokio::spawn(async move {
-                while let Some(d) = rx.recv().await {
-                    println!("+len:{}", rx.len());
-                    let pcm = d;
-                    let adapter = RawAdapter::new(Cursor::new(pcm), 48000, 2);
-                    let input = Input::from(adapter);
-                    // handlerをロックしないように毎回dropさせる
-                    let mut handler = handler_lock.lock().await;
-                    handler.play_input(input);
-                }
-            });
-        }
+    async fn get_ctx(&self) -> Option<Context> {
+        self.ctx.read().await.clone()
     }
-    pub async fn leave(&self, guild_id: GuildId) -> Result<(), String> {
-        let manager = self.get_manager().await;
-        let manager = match manager {
-            None => {
-                return Err("songbird get error".to_owned());
-            }
-            Some(manager) => manager,
-        };
-        let has_handler = manager.get(guild_id).is_some();
-        if has_handler {
-            if let Err(e) = manager.remove(guild_id).await {
-                return Err(e.to_string());
-            }
-        } else {
-            return Err("Not in VC".to_string());
-        }
-        Ok(())
+    pub(crate) fn rest(&self) -> Option<Rest> {
+        self.rest.clone()
     }
-    pub async fn get_voice_channels(&self, guild_id: GuildId) -> Result<Vec<GuildChannel>, String> {
-        let ctx = CTX.get();
-        let ctx_lock = match ctx {
-            None => {
-                return Err("ctx None".to_owned());
-            }
-            Some(ctx) => ctx.clone(),
-        };
-        let ctx = ctx_lock.read().await;
-        let guild = ctx.http.get_guild(guild_id).await.unwrap();
-        let channels = guild.channels(ctx.http.clone()).await.unwrap();
-        let voice_channels: Vec<GuildChannel> = channels
-            .values()
-            .filter(|channel| channel.bitrate.is_some())
-            .cloned()
-            .collect();
-        Ok(voice_channels)
+    // readyが来るまではREST APIも使わない
+    async fn get_rest(&self) -> Result<&Rest, VcError> {
+        match (self.get_ctx().await, self.rest.as_ref()) {
+            (Some(_), Some(rest)) => Ok(rest),
+            _ => Err(VcError::BotNotReady(self.identify.to_string())),
+        }
     }
     async fn get_manager(&self) -> Option<Arc<Songbird>> {
-        let ctx = CTX.get();
-        let ctx_lock = match ctx {
+        let ctx = match self.get_ctx().await {
             None => {
                 error!("ctx None");
                 return None;
             }
-            Some(ctx) => ctx.clone(),
+            Some(ctx) => ctx,
         };
-        let ctx = ctx_lock.read().await;
 
         songbird::get(&ctx).await
     }
 
-    fn create_config(&self) -> Config {
+    fn create_config() -> Config {
         let codec_registry = CODEC_REGISTRY.get_or_init(|| {
             let mut registry = CodecRegistry::new();
             register_enabled_codecs(&mut registry);
@@ -158,3 +211,147 @@ impl Sub {
             .format_registry(probe)
     }
 }
+
+#[async_trait]
+impl VoiceSink for Sub {
+    fn identify(&self) -> &SubIdentify {
+        &self.identify
+    }
+    fn guild_id(&self) -> GuildId {
+        self.guild_id
+    }
+    fn user_id(&self) -> Option<UserId> {
+        self.user_id
+    }
+    fn status(&self) -> BotStatus {
+        self.status.get()
+    }
+    async fn join(
+        &self,
+        join_info: JoinInfo,
+        mut rx: VoiceReceiverType,
+        tasks: &TaskScope,
+    ) -> Result<(), VcError> {
+        let ctx = self
+            .get_ctx()
+            .await
+            .ok_or_else(|| VcError::BotNotReady(self.identify.to_string()))?;
+        // 実況VCでは再生もするのでSpeakも必要
+        check_permissions(
+            &ctx,
+            &self.identify.0,
+            join_info,
+            Permissions::CONNECT | Permissions::SPEAK,
+        )?;
+        let manager = match self.get_manager().await {
+            None => {
+                error!("songbird get error");
+                return Err(VcError::BotNotReady(self.identify.to_string()));
+            }
+            Some(manager) => manager,
+        };
+        let handler_lock = match manager.join(join_info.guild_id, join_info.channel_id).await {
+            Ok(handler_lock) => handler_lock,
+            Err(e) => {
+                error!("failed to join vc:{:?}", e);
+                self.status.set(BotState::Failed);
+                return Err(VcError::Voice {
+                    name: self.identify.to_string(),
+                    reason: e.to_string(),
+                });
+            }
+        };
+        self.status.set(BotState::InChannel);
+        let reconnector = Reconnector::new(
+            self.identify.to_string(),
+            manager.clone(),
+            join_info,
+            Arc::new(|handler: &mut Call| handler.set_config(Sub::create_config())),
+            self.status.clone(),
+            tasks.clone(),
+        );
+        {
+            let mut handler = handler_lock.lock().await;
+            reconnector.setup(&mut handler);
+        }
+        if let Some(old) = self.reconnector.write().await.replace(reconnector) {
+            old.stop();
+        }
+        tasks.spawn(async move {
+            while let Some(pcm) = rx.recv().await {
+                let adapter = RawAdapter::new(Cursor::new(pcm), 48000, 2);
+                let input = Input::from(adapter);
+                // handlerをロックしないように毎回dropさせる
+                let mut handler = handler_lock.lock().await;
+                handler.play_input(input);
+            }
+        });
+        Ok(())
+    }
+    async fn leave(&self, guild_id: GuildId) -> Result<(), VcError> {
+        // 意図した退出なので再接続しない(gatewayが止まっていても止める)
+        if let Some(reconnector) = self.reconnector.write().await.take() {
+            reconnector.stop();
+        }
+        let manager = self.get_manager().await;
+        let manager = match manager {
+            None => {
+                return Err(VcError::BotNotReady(self.identify.to_string()));
+            }
+            Some(manager) => manager,
+        };
+        let has_handler = manager.get(guild_id).is_some();
+        if has_handler {
+            if let Err(e) = manager.remove(guild_id).await {
+                return Err(VcError::Voice {
+                    name: self.identify.to_string(),
+                    reason: e.to_string(),
+                });
+            }
+        } else {
+            return Err(VcError::NotInVc(self.identify.to_string()));
+        }
+        self.status.set(BotState::Ready);
+        Ok(())
+    }
+    async fn get_voice_channels(&self, guild_id: GuildId) -> Result<Vec<GuildChannel>, VcError> {
+        fetch_voice_channels(self.get_rest().await?, guild_id).await
+    }
+    async fn get_channels(&self, guild_id: GuildId) -> Result<GuildChannelsType, VcError> {
+        fetch_channels(self.get_rest().await?, guild_id).await
+    }
+    // readyイベントを受け取ったか
+    async fn is_ready(&self) -> bool {
+        self.get_ctx().await.is_some()
+    }
+    async fn switch_channel(&self, channel_id: ChannelId) -> Result<(), VcError> {
+        let ctx = self
+            .get_ctx()
+            .await
+            .ok_or_else(|| VcError::BotNotReady(self.identify.to_string()))?;
+        let join_info = JoinInfo {
+            guild_id: self.guild_id,
+            channel_id,
+        };
+        check_permissions(
+            &ctx,
+            &self.identify.0,
+            join_info,
+            Permissions::CONNECT | Permissions::SPEAK,
+        )?;
+        match Reconnector::switch_channel(&self.reconnector, channel_id).await {
+            Some(Ok(())) => Ok(()),
+            Some(Err(e)) => Err(VcError::Voice {
+                name: self.identify.to_string(),
+                reason: e.to_string(),
+            }),
+            None => Err(VcError::NotInVc(self.identify.to_string())),
+        }
+    }
+    async fn shutdown(&self) {
+        self.status.set(BotState::Stopped);
+        if let Some(shard_manager) = &self.shard_manager {
+            shard_manager.shutdown_all().await;
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildChannel};
+
+use super::{error::VcError, types::SessionId};
+
+// 試合番号からチャンネルを探すルール
+// {n}は試合番号，{A,B}のような候補はspeakers(listeners)の順に割り当てる
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DiscoveryRule {
+    // このカテゴリの中から探す(例: "Match {n}")．省略時は全チャンネル
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    // 選手VCの名前(例: "Team-{A,B}-Match-{n}")
+    pub speakers: String,
+    // 実況VCの名前．省略時はlistenerのチャンネルは変えない
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listeners: Option<String>,
+}
+
+impl DiscoveryRule {
+    // 候補の{...}は名前に1つまで，カテゴリには書けない(どのBotに割り当てるか決まらない)
+    pub fn validate(&self, session: &SessionId) -> Result<(), VcError> {
+        let invalid = |reason: String| Err(VcError::Config(format!("{}: {}", session, reason)));
+        if let Some(category) = &self.category {
+            if !groups(category).is_empty() {
+                return invalid(format!(
+                    "discovery category {} cannot have {{...}}",
+                    category
+                ));
+            }
+        }
+        for pattern in std::iter::once(&self.speakers).chain(&self.listeners) {
+            if groups(pattern).len() > 1 {
+                return invalid(format!(
+                    "discovery pattern {} has more than one {{...}}",
+                    pattern
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// {n}以外の{...}の位置
+fn groups(pattern: &str) -> Vec<(usize, usize)> {
+    let mut groups = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = pattern[offset..].find('{') {
+        let start = offset + start;
+        let end = match pattern[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        if &pattern[start + 1..end] != "n" {
+            groups.push((start, end));
+        }
+        offset = end + 1;
+    }
+    groups
+}
+
+// {n}以外の{...}を展開する(validateで1つまでにしている)
+// "Team-{A,B}-Match-{n}" -> ["Team-A-Match-{n}", "Team-B-Match-{n}"]
+pub fn expand(pattern: &str) -> Vec<String> {
+    match groups(pattern).first() {
+        Some(&(start, end)) => pattern[start + 1..end]
+            .split(',')
+            .map(|alt| format!("{}{}{}", &pattern[..start], alt.trim(), &pattern[end + 1..]))
+            .collect(),
+        None => vec![pattern.to_owned()],
+    }
+}
+
+// index番目のBotの候補(候補が1つなら全てのBotで同じ名前)
+pub fn name_for(names: &[String], index: usize) -> Option<&String> {
+    match names {
+        [name] => Some(name),
+        names => names.get(index),
+    }
+}
+
+// 名前(とカテゴリ)が一致するVCを探す．大文字小文字は区別しない
+pub fn find_channel(
+    channels: &[GuildChannel],
+    categories: &HashMap<ChannelId, String>,
+    category: Option<&str>,
+    name: &str,
+) -> Option<ChannelId> {
+    channels
+        .iter()
+        .filter(|channel| channel.name.eq_ignore_ascii_case(name))
+        .find(|channel| match category {
+            None => true,
+            Some(category) => channel
+                .parent_id
+                .and_then(|parent_id| categories.get(&parent_id))
+                .is_some_and(|parent| parent.eq_ignore_ascii_case(category)),
+        })
+        .map(|channel| channel.id)
+}
+
+pub fn fill(pattern: &str, match_no: &str) -> String {
+    pattern.replace("{n}", match_no)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(category: Option<&str>, speakers: &str, listeners: Option<&str>) -> DiscoveryRule {
+        DiscoveryRule {
+            category: category.map(str::to_owned),
+            speakers: speakers.to_owned(),
+            listeners: listeners.map(str::to_owned),
+        }
+    }
+
+    fn channel(id: u64, name: &str, parent_id: Option<u64>) -> GuildChannel {
+        let mut channel = GuildChannel::default();
+        channel.id = ChannelId::new(id);
+        channel.name = name.to_owned();
+        channel.parent_id = parent_id.map(ChannelId::new);
+        channel
+    }
+
+    #[test]
+    fn group_is_expanded_and_n_is_filled() {
+        let names = expand("Team-{A, B}-Match-{n}");
+        assert_eq!(names, vec!["Team-A-Match-{n}", "Team-B-Match-{n}"]);
+        assert_eq!(fill(&names[1], "3"), "Team-B-Match-3");
+        assert_eq!(expand("Caster-{n}"), vec!["Caster-{n}"]);
+        assert_eq!(fill("Match {n} / {n}", "12"), "Match 12 / 12");
+    }
+
+    #[test]
+    fn names_are_assigned_by_index() {
+        let names = expand("Team-{A,B}");
+        assert_eq!(name_for(&names, 0).unwrap(), "Team-A");
+        assert_eq!(name_for(&names, 1).unwrap(), "Team-B");
+        assert_eq!(name_for(&names, 2), None);
+        // 候補が1つなら全てのBotで同じ名前
+        let names = expand("Caster-{n}");
+        assert_eq!(name_for(&names, 0).unwrap(), "Caster-{n}");
+        assert_eq!(name_for(&names, 5).unwrap(), "Caster-{n}");
+    }
+
+    #[test]
+    fn channel_is_found_in_the_category_ignoring_case() {
+        let categories = HashMap::from([
+            (ChannelId::new(100), "Match 3".to_owned()),
+            (ChannelId::new(200), "Match 4".to_owned()),
+        ]);
+        let channels = vec![
+            channel(1, "Team-A-Match-3", Some(200)),
+            channel(2, "team-a-match-3", Some(100)),
+            channel(3, "Team-B-Match-3", None),
+        ];
+        assert_eq!(
+            find_channel(&channels, &categories, Some("MATCH 3"), "Team-A-Match-3"),
+            Some(ChannelId::new(2))
+        );
+        assert_eq!(
+            find_channel(&channels, &categories, Some("Match 4"), "TEAM-A-MATCH-3"),
+            Some(ChannelId::new(1))
+        );
+        // カテゴリに入っていないVCはカテゴリを指定すると見つからない
+        assert_eq!(
+            find_channel(&channels, &categories, Some("Match 3"), "Team-B-Match-3"),
+            None
+        );
+        assert_eq!(
+            find_channel(&channels, &categories, None, "Team-B-Match-3"),
+            Some(ChannelId::new(3))
+        );
+    }
+
+    #[test]
+    fn more_than_one_group_is_rejected() {
+        let session = SessionId("main".to_owned());
+        assert!(rule(
+            Some("Match {n}"),
+            "Team-{A,B}-Match-{n}",
+            Some("Caster-{n}")
+        )
+        .validate(&session)
+        .is_ok());
+        assert!(rule(None, "Team-{A,B}-{Red,Blue}-{n}", None)
+            .validate(&session)
+            .is_err());
+        assert!(rule(None, "Team-{A,B}", Some("Caster-{1,2}-{x,y}"))
+            .validate(&session)
+            .is_err());
+        assert!(rule(Some("Match {A,B}"), "Team-{A,B}", None)
+            .validate(&session)
+            .is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+
+use confy::ConfyError;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use serenity::all::ChannelId;
+
+use super::types::{PubIdentify, SessionId, SubIdentify};
+
+// コマンドのエラー．フロントエンドには{ code, message }で返す
+#[derive(Debug, Clone)]
+pub enum VcError {
+    UnknownSession(SessionId),
+    UnknownTrack(PubIdentify),
+    UnknownListener(SubIdentify),
+    UnknownPreset(String),
+    UnknownMatch(String),
+    AlreadyExists(String),
+    InvalidChannelId(String),
+    // bot_poolに空きが無い
+    NoPooledBot(String),
+    // Tokenが無効など
+    BotStart {
+        name: String,
+        reason: String,
+    },
+    // readyイベントがまだ来ていない
+    BotNotReady(String),
+    NotInVc(String),
+    MissingPermission {
+        name: String,
+        channel_id: ChannelId,
+        permission: String,
+    },
+    // songbirdのjoin/leave失敗
+    Voice {
+        name: String,
+        reason: String,
+    },
+    Discord(String),
+    Config(String),
+    // スケジュールの読み込み失敗など
+    Schedule(String),
+    Internal(String),
+}
+
+impl VcError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            VcError::UnknownSession(_) => "unknown_session",
+            VcError::UnknownTrack(_) => "unknown_track",
+            VcError::UnknownListener(_) => "unknown_listener",
+            VcError::UnknownPreset(_) => "unknown_preset",
+            VcError::UnknownMatch(_) => "unknown_match",
+            VcError::AlreadyExists(_) => "already_exists",
+            VcError::InvalidChannelId(_) => "invalid_channel_id",
+            VcError::NoPooledBot(_) => "no_pooled_bot",
+            VcError::BotStart { .. } => "bot_start_failed",
+            VcError::BotNotReady(_) => "bot_not_ready",
+            VcError::NotInVc(_) => "not_in_vc",
+            VcError::MissingPermission { .. } => "missing_permission",
+            VcError::Voice { .. } => "voice_error",
+            VcError::Discord(_) => "discord_error",
+            VcError::Config(_) => "config_error",
+            VcError::Schedule(_) => "schedule_error",
+            VcError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl fmt::Display for VcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VcError::UnknownSession(session_id) => write!(f, "unknown session: {}", session_id),
+            VcError::UnknownTrack(track) => write!(f, "unknown track: {}", track),
+            VcError::UnknownListener(name) => write!(f, "unknown listener: {}", name),
+            VcError::UnknownPreset(name) => write!(f, "unknown preset: {}", name),
+            VcError::UnknownMatch(match_id) => write!(f, "unknown match: {}", match_id),
+            VcError::AlreadyExists(name) => write!(f, "{} already exists", name),
+            VcError::InvalidChannelId(channel_id) => {
+                write!(f, "invalid channel id: {:?}", channel_id)
+            }
+            VcError::NoPooledBot(name) => write!(f, "{}: no bot left in bot_pool", name),
+            VcError::BotStart { name, reason } => write!(f, "{} token error: {}", name, reason),
+            VcError::BotNotReady(name) => write!(f, "{} is not ready", name),
+            VcError::NotInVc(name) => write!(f, "{} is not in VC", name),
+            VcError::MissingPermission {
+                name,
+                channel_id,
+                permission,
+            } => write!(
+                f,
+                "{} lacks {} permission in {}",
+                name, permission, channel_id
+            ),
+            VcError::Voice { name, reason } => write!(f, "{} voice error: {}", name, reason),
+            VcError::Discord(reason) => write!(f, "Discord API error: {}", reason),
+            VcError::Config(reason) => write!(f, "Config error: {}", reason),
+            VcError::Schedule(reason) => write!(f, "Schedule error: {}", reason),
+            VcError::Internal(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for VcError {}
+
+impl Serialize for VcError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("VcError", 2)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+impl From<serenity::Error> for VcError {
+    fn from(e: serenity::Error) -> Self {
+        VcError::Discord(e.to_string())
+    }
+}
+
+impl From<ConfyError> for VcError {
+    fn from(e: ConfyError) -> Self {
+        VcError::Config(e.to_string())
+    }
+}
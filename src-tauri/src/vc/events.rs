@@ -0,0 +1,14 @@
+// VoiceManagerからのイベントの通知先
+// GUIではフロントエンド，CLIでは標準出力に送る
+pub trait EventEmitter: Send + Sync {
+    fn emit(&self, event: &str, payload: serde_json::Value);
+}
+
+// 1イベント1行で標準出力に出す
+pub struct StdoutEmitter;
+
+impl EventEmitter for StdoutEmitter {
+    fn emit(&self, event: &str, payload: serde_json::Value) {
+        println!("{} {}", event, payload);
+    }
+}
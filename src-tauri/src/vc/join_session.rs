@@ -0,0 +1,45 @@
+use std::{collections::HashMap, future::Future};
+
+use serenity::all::ChannelId;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use super::types::{PubIdentify, SubIdentify, VoiceManagerSenderType};
+
+// Join中に起動したtask．leaveでまとめて止める
+#[derive(Clone, Default)]
+pub struct TaskScope {
+    cancel: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl TaskScope {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let cancel = self.cancel.clone();
+        self.tracker.spawn(async move {
+            tokio::select! {
+                _ = cancel.cancelled() => {}
+                _ = task => {}
+            }
+        });
+    }
+    // 全てのtaskを止めて終わるまで待つ
+    pub async fn shutdown(&self) {
+        self.cancel.cancel();
+        self.tracker.close();
+        self.tracker.wait().await;
+    }
+}
+
+// Join中の状態(failover時に同じチャンネルに入り直す)
+pub struct JoinSession {
+    pub manager_tx: VoiceManagerSenderType,
+    pub pub_infos: HashMap<PubIdentify, ChannelId>,
+    pub sub_infos: HashMap<SubIdentify, ChannelId>,
+    pub tasks: TaskScope,
+}
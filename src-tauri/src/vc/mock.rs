@@ -0,0 +1,1116 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serenity::{
+    all::{ChannelId, GuildChannel, GuildId, UserId},
+    async_trait,
+};
+use songbird::model::id::UserId as VoiceUserId;
+
+use super::{
+    backend::{VoiceBackend, VoiceSink, VoiceSource},
+    directory::MemberInfo,
+    dis_pub::Roster,
+    error::VcError,
+    join_session::TaskScope,
+    relay::RelayMetrics,
+    status::{BotState, BotStatus, StatusReporter},
+    types::{
+        AnchorMoved, AnchorMovedSenderType, DeadBot, DeadBotSenderType, GuildChannelsType,
+        JoinInfo, PubIdentify, SendEnum, SpeakingInfo, SubIdentify, UserInfo,
+        VoiceManagerSenderType, VoiceReceiverType, VoiceType, VoiceUserEvent,
+    },
+};
+
+struct MockSourceState {
+    is_listening: AtomicBool,
+    // Join中のVCとVoiceManagerへの送信先
+    joined: Mutex<Option<(JoinInfo, VoiceManagerSenderType)>>,
+    dead_tx: DeadBotSenderType,
+    anchor: Mutex<Option<UserId>>,
+    anchor_tx: AnchorMovedSenderType,
+    status: StatusReporter,
+    unjoinable: Unjoinable,
+    // Join中のチャンネルにいるユーザー(Pubと同じRosterで通知する)
+    roster: Roster,
+    present: Present,
+}
+
+struct MockSinkState {
+    joined: Mutex<Option<JoinInfo>>,
+    // 再生したpcm f32の音声データ
+    received: Arc<Mutex<Vec<Vec<u8>>>>,
+    dead_tx: DeadBotSenderType,
+    status: StatusReporter,
+    unjoinable: Unjoinable,
+}
+
+// 入れないことにするVC(権限が無い場合など)
+type Unjoinable = Arc<Mutex<HashSet<ChannelId>>>;
+// VCごとに，話していなくても入っているユーザー
+type Present = Arc<Mutex<HashMap<ChannelId, Vec<UserId>>>>;
+
+fn check_joinable(
+    unjoinable: &Unjoinable,
+    name: &str,
+    channel_id: ChannelId,
+) -> Result<(), VcError> {
+    if unjoinable.lock().unwrap().contains(&channel_id) {
+        return Err(VcError::Voice {
+            name: name.to_owned(),
+            reason: format!("cannot join {}", channel_id),
+        });
+    }
+    Ok(())
+}
+
+pub struct MockSource {
+    identify: PubIdentify,
+    guild_id: GuildId,
+    state: Arc<MockSourceState>,
+}
+
+pub struct MockSink {
+    identify: SubIdentify,
+    guild_id: GuildId,
+    state: Arc<MockSinkState>,
+}
+
+// Discordを使わずにjoin/leave，音量，聞き取りの動作を確かめるためのbackend
+// Tokenは何でもよい．選手VCの発話はspeak()で流し込む
+pub struct MockBackend {
+    // failoverで起動し直しても古いBotは残す(退出しなければ音声を送り続ける)
+    sources: Mutex<HashMap<PubIdentify, Vec<Arc<MockSourceState>>>>,
+    sinks: Mutex<HashMap<SubIdentify, Arc<MockSinkState>>>,
+    user_names: Mutex<HashMap<UserId, String>>,
+    // member()の応答にかかる時間と呼ばれた回数
+    member_delay: Mutex<Duration>,
+    member_calls: AtomicUsize,
+    unjoinable: Unjoinable,
+    present: Present,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        MockBackend {
+            sources: Mutex::new(HashMap::new()),
+            sinks: Mutex::new(HashMap::new()),
+            user_names: Mutex::new(HashMap::new()),
+            member_delay: Mutex::new(Duration::ZERO),
+            member_calls: AtomicUsize::new(0),
+            unjoinable: Unjoinable::default(),
+            present: Present::default(),
+        }
+    }
+    pub fn set_user_name(&self, user_id: UserId, name: &str) {
+        let mut user_names = self.user_names.lock().unwrap();
+        user_names.insert(user_id, name.to_owned());
+    }
+    pub fn set_member_delay(&self, delay: Duration) {
+        *self.member_delay.lock().unwrap() = delay;
+    }
+    pub fn member_calls(&self) -> usize {
+        self.member_calls.load(Ordering::SeqCst)
+    }
+    // Joinする前からVCにいるユーザー
+    pub fn set_present(&self, channel_id: ChannelId, users: Vec<UserId>) {
+        self.present.lock().unwrap().insert(channel_id, users);
+    }
+    // このVCへのjoin/switch_channelを失敗させる
+    pub fn set_unjoinable(&self, channel_id: ChannelId) {
+        self.unjoinable.lock().unwrap().insert(channel_id);
+    }
+    // trackのVCにいるBot(Join中のVC，VoiceManagerへの送信先，聞き取り中か)
+    fn source_joined(
+        &self,
+        track: &PubIdentify,
+    ) -> Result<Vec<(JoinInfo, VoiceManagerSenderType, bool)>, VcError> {
+        let sources = self.sources.lock().unwrap();
+        let states = sources
+            .get(track)
+            .ok_or_else(|| VcError::UnknownTrack(track.clone()))?;
+        let joined: Vec<_> = states
+            .iter()
+            .filter_map(|state| {
+                let joined = state.joined.lock().unwrap();
+                let (join_info, tx) = joined.as_ref()?;
+                let is_listening = state.is_listening.load(Ordering::SeqCst);
+                Some((*join_info, tx.clone(), is_listening))
+            })
+            .collect();
+        if joined.is_empty() {
+            return Err(VcError::NotInVc(track.to_string()));
+        }
+        Ok(joined)
+    }
+    async fn send(tx: &VoiceManagerSenderType, data: SendEnum) -> Result<(), VcError> {
+        tx.send(data)
+            .await
+            .map_err(|e| VcError::Internal(e.to_string()))
+    }
+    pub async fn user_join(
+        &self,
+        track: &PubIdentify,
+        user_id: VoiceUserId,
+    ) -> Result<(), VcError> {
+        for (join_info, tx, _) in self.source_joined(track)? {
+            let user_info = UserInfo {
+                user_id,
+                event: VoiceUserEvent::Join,
+                identify: track.clone(),
+                guild_id: join_info.guild_id,
+                member: None,
+            };
+            MockBackend::send(&tx, SendEnum::UserData(user_info)).await?;
+        }
+        Ok(())
+    }
+    pub async fn user_leave(
+        &self,
+        track: &PubIdentify,
+        user_id: VoiceUserId,
+    ) -> Result<(), VcError> {
+        for (join_info, tx, _) in self.source_joined(track)? {
+            let user_info = UserInfo {
+                user_id,
+                event: VoiceUserEvent::Leave,
+                identify: track.clone(),
+                guild_id: join_info.guild_id,
+                member: None,
+            };
+            MockBackend::send(&tx, SendEnum::UserData(user_info)).await?;
+        }
+        Ok(())
+    }
+    // 選手VCで1tick分話したことにする
+    // 聞き取り中でなければVoiceManagerに音声は送らない(Receiverと同じ)
+    pub async fn speak(
+        &self,
+        track: &PubIdentify,
+        user_id: VoiceUserId,
+        pcm: Vec<i16>,
+    ) -> Result<(), VcError> {
+        for (_, tx, is_listening) in self.source_joined(track)? {
+            let speaking_info = SpeakingInfo {
+                user_id,
+                speaking: true,
+            };
+            MockBackend::send(&tx, SendEnum::SpeakingState(speaking_info)).await?;
+            if is_listening {
+                let voice_data = VoiceType::new(user_id, track.clone(), pcm.clone());
+                MockBackend::send(&tx, SendEnum::VoiceData(voice_data)).await?;
+            }
+        }
+        Ok(())
+    }
+    // 最後に起動したBotのVC
+    pub fn source_channel(&self, track: &PubIdentify) -> Option<ChannelId> {
+        let sources = self.sources.lock().unwrap();
+        let joined = sources.get(track)?.last()?.joined.lock().unwrap();
+        joined.as_ref().map(|(join_info, _)| join_info.channel_id)
+    }
+    pub fn sink_channel(&self, listener: &SubIdentify) -> Option<ChannelId> {
+        let sinks = self.sinks.lock().unwrap();
+        let joined = sinks.get(listener)?.joined.lock().unwrap();
+        joined.map(|join_info| join_info.channel_id)
+    }
+    // listenerが再生した音声を取り出す
+    pub fn take_received(&self, listener: &SubIdentify) -> Vec<Vec<u8>> {
+        let sinks = self.sinks.lock().unwrap();
+        match sinks.get(listener) {
+            Some(state) => std::mem::take(&mut *state.received.lock().unwrap()),
+            None => Vec::new(),
+        }
+    }
+    // ユーザーが別のVCに移った(Noneなら抜けた)ことにする(voice_state_update)
+    // Pubと同じく，VCから抜けただけならanchor_txには通知しない
+    pub fn move_user(&self, user_id: UserId, channel_id: Option<ChannelId>) {
+        let channel_id = match channel_id {
+            Some(channel_id) => channel_id,
+            None => return,
+        };
+        let sources = self.sources.lock().unwrap();
+        for (track, state) in sources
+            .iter()
+            .flat_map(|(track, states)| states.iter().map(move |state| (track, state)))
+        {
+            if *state.anchor.lock().unwrap() != Some(user_id) {
+                continue;
+            }
+            let joined = state.joined.lock().unwrap();
+            if joined
+                .as_ref()
+                .is_some_and(|(join_info, _)| join_info.channel_id != channel_id)
+            {
+                let _ = state.anchor_tx.send(AnchorMoved {
+                    track: track.clone(),
+                    channel_id,
+                });
+            }
+        }
+    }
+    // gatewayが落ちたことにする
+    pub fn kill_source(&self, track: &PubIdentify) {
+        let sources = self.sources.lock().unwrap();
+        if let Some(state) = sources.get(track).and_then(|states| states.last()) {
+            state.status.set(BotState::Failed);
+            let _ = state.dead_tx.send(DeadBot::Speaker(track.clone()));
+        }
+    }
+    pub fn kill_sink(&self, listener: &SubIdentify) {
+        let sinks = self.sinks.lock().unwrap();
+        if let Some(state) = sinks.get(listener) {
+            state.status.set(BotState::Failed);
+            let _ = state.dead_tx.send(DeadBot::Listener(listener.clone()));
+        }
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl VoiceBackend for MockBackend {
+    async fn start_source(
+        &self,
+        identify: PubIdentify,
+        guild_id: GuildId,
+        _token: &str,
+        dead_tx: DeadBotSenderType,
+        anchor_tx: AnchorMovedSenderType,
+        status: StatusReporter,
+    ) -> Result<Box<dyn VoiceSource>, VcError> {
+        // gatewayが無いのですぐにreadyになる
+        status.set_ready();
+        let state = Arc::new(MockSourceState {
+            is_listening: AtomicBool::new(false),
+            joined: Mutex::new(None),
+            dead_tx,
+            anchor: Mutex::new(None),
+            anchor_tx,
+            status,
+            unjoinable: self.unjoinable.clone(),
+            roster: Roster::default(),
+            present: self.present.clone(),
+        });
+        let mut sources = self.sources.lock().unwrap();
+        sources
+            .entry(identify.clone())
+            .or_default()
+            .push(state.clone());
+        Ok(Box::new(MockSource {
+            identify,
+            guild_id,
+            state,
+        }))
+    }
+    async fn start_sink(
+        &self,
+        identify: SubIdentify,
+        guild_id: GuildId,
+        _token: &str,
+        dead_tx: DeadBotSenderType,
+        status: StatusReporter,
+    ) -> Result<Box<dyn VoiceSink>, VcError> {
+        status.set_ready();
+        let state = Arc::new(MockSinkState {
+            joined: Mutex::new(None),
+            received: Arc::new(Mutex::new(Vec::new())),
+            dead_tx,
+            status,
+            unjoinable: self.unjoinable.clone(),
+        });
+        let mut sinks = self.sinks.lock().unwrap();
+        sinks.insert(identify.clone(), state.clone());
+        Ok(Box::new(MockSink {
+            identify,
+            guild_id,
+            state,
+        }))
+    }
+    async fn member(&self, _guild_id: GuildId, user_id: UserId) -> Result<MemberInfo, VcError> {
+        self.member_calls.fetch_add(1, Ordering::SeqCst);
+        let delay = *self.member_delay.lock().unwrap();
+        tokio::time::sleep(delay).await;
+        let user_names = self.user_names.lock().unwrap();
+        let name = user_names
+            .get(&user_id)
+            .ok_or_else(|| VcError::Internal(format!("unknown user: {}", user_id)))?;
+        Ok(MemberInfo {
+            display_name: name.clone(),
+            username: name.clone(),
+            ..MemberInfo::unknown(user_id)
+        })
+    }
+}
+
+#[async_trait]
+impl VoiceSource for MockSource {
+    fn identify(&self) -> &PubIdentify {
+        &self.identify
+    }
+    fn guild_id(&self) -> GuildId {
+        self.guild_id
+    }
+    fn user_id(&self) -> Option<UserId> {
+        None
+    }
+    fn status(&self) -> BotStatus {
+        self.state.status.get()
+    }
+    async fn is_ready(&self) -> bool {
+        true
+    }
+    async fn get_voice_channels(&self, _guild_id: GuildId) -> Result<Vec<GuildChannel>, VcError> {
+        Ok(Vec::new())
+    }
+    async fn get_channels(&self, _guild_id: GuildId) -> Result<GuildChannelsType, VcError> {
+        Ok((Vec::new(), HashMap::new()))
+    }
+    async fn join(
+        &self,
+        join_info: JoinInfo,
+        tx: VoiceManagerSenderType,
+        _metrics: Arc<RelayMetrics>,
+        _tasks: &TaskScope,
+    ) -> Result<(), VcError> {
+        check_joinable(
+            &self.state.unjoinable,
+            &self.identify.0,
+            join_info.channel_id,
+        )?;
+        self.state.roster.start(tx.clone());
+        {
+            let mut joined = self.state.joined.lock().unwrap();
+            *joined = Some((join_info, tx));
+            self.state.status.set(BotState::InChannel);
+        }
+        self.sync_roster(join_info.channel_id).await;
+        Ok(())
+    }
+    async fn leave(&self, _guild_id: GuildId) -> Result<(), VcError> {
+        self.state.roster.clear();
+        let mut joined = self.state.joined.lock().unwrap();
+        match joined.take() {
+            Some(_) => {
+                self.state.status.set(BotState::Ready);
+                Ok(())
+            }
+            None => Err(VcError::NotInVc(self.identify.to_string())),
+        }
+    }
+    fn set_is_listening(&self, is_listening: bool) {
+        self.state
+            .is_listening
+            .store(is_listening, Ordering::SeqCst);
+    }
+    fn is_listening(&self) -> bool {
+        self.state.is_listening.load(Ordering::SeqCst)
+    }
+    fn set_anchor(&self, anchor: Option<UserId>) {
+        *self.state.anchor.lock().unwrap() = anchor;
+    }
+    fn anchor(&self) -> Option<UserId> {
+        *self.state.anchor.lock().unwrap()
+    }
+    async fn switch_channel(&self, channel_id: ChannelId) -> Result<(), VcError> {
+        // 移れなかった場合は元のVCに残る
+        check_joinable(&self.state.unjoinable, &self.identify.0, channel_id)?;
+        {
+            let mut joined = self.state.joined.lock().unwrap();
+            match joined.as_mut() {
+                Some((join_info, _)) => join_info.channel_id = channel_id,
+                None => return Err(VcError::NotInVc(self.identify.to_string())),
+            }
+        }
+        self.sync_roster(channel_id).await;
+        Ok(())
+    }
+    async fn shutdown(&self) {
+        self.state.status.set(BotState::Stopped);
+    }
+}
+
+impl MockSource {
+    async fn sync_roster(&self, channel_id: ChannelId) {
+        let present = {
+            let present = self.state.present.lock().unwrap();
+            present
+                .get(&channel_id)
+                .into_iter()
+                .flatten()
+                .map(|user_id| (*user_id, None))
+                .collect()
+        };
+        self.state
+            .roster
+            .sync_present(&self.identify, self.guild_id, present)
+            .await;
+    }
+}
+
+#[async_trait]
+impl VoiceSink for MockSink {
+    fn identify(&self) -> &SubIdentify {
+        &self.identify
+    }
+    fn guild_id(&self) -> GuildId {
+        self.guild_id
+    }
+    fn user_id(&self) -> Option<UserId> {
+        None
+    }
+    fn status(&self) -> BotStatus {
+        self.state.status.get()
+    }
+    async fn is_ready(&self) -> bool {
+        true
+    }
+    async fn get_voice_channels(&self, _guild_id: GuildId) -> Result<Vec<GuildChannel>, VcError> {
+        Ok(Vec::new())
+    }
+    async fn get_channels(&self, _guild_id: GuildId) -> Result<GuildChannelsType, VcError> {
+        Ok((Vec::new(), HashMap::new()))
+    }
+    async fn join(
+        &self,
+        join_info: JoinInfo,
+        mut rx: VoiceReceiverType,
+        tasks: &TaskScope,
+    ) -> Result<(), VcError> {
+        check_joinable(
+            &self.state.unjoinable,
+            &self.identify.0,
+            join_info.channel_id,
+        )?;
+        {
+            let mut joined = self.state.joined.lock().unwrap();
+            *joined = Some(join_info);
+            self.state.status.set(BotState::InChannel);
+        }
+        let received = self.state.received.clone();
+        tasks.spawn(async move {
+            while let Some(pcm) = rx.recv().await {
+                received.lock().unwrap().push(pcm);
+            }
+        });
+        Ok(())
+    }
+    async fn leave(&self, _guild_id: GuildId) -> Result<(), VcError> {
+        let mut joined = self.state.joined.lock().unwrap();
+        match joined.take() {
+            Some(_) => {
+                self.state.status.set(BotState::Ready);
+                Ok(())
+            }
+            None => Err(VcError::NotInVc(self.identify.to_string())),
+        }
+    }
+    async fn switch_channel(&self, channel_id: ChannelId) -> Result<(), VcError> {
+        // 移れなかった場合は元のVCに残る
+        check_joinable(&self.state.unjoinable, &self.identify.0, channel_id)?;
+        let mut joined = self.state.joined.lock().unwrap();
+        match joined.as_mut() {
+            Some(join_info) => {
+                join_info.channel_id = channel_id;
+                Ok(())
+            }
+            None => Err(VcError::NotInVc(self.identify.to_string())),
+        }
+    }
+    async fn shutdown(&self) {
+        self.state.status.set(BotState::Stopped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::vc::{
+        bot_pool::BotPool,
+        config::{MatchPreset, SessionConfig},
+        events::EventEmitter,
+        overlay::Overlay,
+        relay::{relay_channel, BackpressurePolicy},
+        status::{BotRole, BotState},
+        types::SessionId,
+        vc_client::VC,
+    };
+
+    fn status(role: BotRole, name: &str) -> StatusReporter {
+        let emitter = Arc::new(RecordingEmitter::default());
+        StatusReporter::new(SessionId("main".to_owned()), role, name.to_owned(), emitter)
+    }
+
+    fn join_info(channel_id: u64) -> JoinInfo {
+        JoinInfo {
+            guild_id: GuildId::new(1),
+            channel_id: ChannelId::new(channel_id),
+        }
+    }
+
+    #[tokio::test]
+    async fn source_sends_voice_only_while_listening() {
+        let backend = MockBackend::new();
+        let track = PubIdentify("Track1".to_owned());
+        let (dead_tx, _dead_rx) = mpsc::unbounded_channel();
+        let source = backend
+            .start_source(
+                track.clone(),
+                GuildId::new(1),
+                "mock",
+                dead_tx,
+                mpsc::unbounded_channel().0,
+                status(BotRole::Speaker, "Track1"),
+            )
+            .await
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(16);
+        let tasks = TaskScope::new();
+        source
+            .join(join_info(10), tx, Default::default(), &tasks)
+            .await
+            .unwrap();
+        assert_eq!(backend.source_channel(&track), Some(ChannelId::new(10)));
+
+        backend.user_join(&track, VoiceUserId(42)).await.unwrap();
+        match rx.recv().await {
+            Some(SendEnum::UserData(user_info)) => {
+                assert_eq!(user_info.event, VoiceUserEvent::Join);
+                assert_eq!(user_info.user_id, VoiceUserId(42));
+            }
+            _ => panic!("expected UserData"),
+        }
+
+        // 聞き取り中でなければ発話の状態だけが来る
+        backend
+            .speak(&track, VoiceUserId(42), vec![0; 4])
+            .await
+            .unwrap();
+        assert!(matches!(rx.recv().await, Some(SendEnum::SpeakingState(_))));
+        assert!(rx.try_recv().is_err());
+
+        source.set_is_listening(true);
+        backend
+            .speak(&track, VoiceUserId(42), vec![0; 4])
+            .await
+            .unwrap();
+        assert!(matches!(rx.recv().await, Some(SendEnum::SpeakingState(_))));
+        match rx.recv().await {
+            Some(SendEnum::VoiceData(voice)) => assert_eq!(voice.voice_data, vec![0; 4]),
+            _ => panic!("expected VoiceData"),
+        }
+
+        source.leave(GuildId::new(1)).await.unwrap();
+        assert_eq!(backend.source_channel(&track), None);
+        assert!(backend.user_join(&track, VoiceUserId(42)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sink_records_played_voice() {
+        let backend = MockBackend::new();
+        let listener = SubIdentify("Listener".to_owned());
+        let (dead_tx, _dead_rx) = mpsc::unbounded_channel();
+        let sink = backend
+            .start_sink(
+                listener.clone(),
+                GuildId::new(1),
+                "mock",
+                dead_tx,
+                status(BotRole::Listener, "Listener"),
+            )
+            .await
+            .unwrap();
+        let (tx, rx) = relay_channel(BackpressurePolicy::default(), Default::default());
+        let tasks = TaskScope::new();
+        sink.join(join_info(20), rx, &tasks).await.unwrap();
+        assert_eq!(backend.sink_channel(&listener), Some(ChannelId::new(20)));
+
+        tx.send(vec![1, 2, 3, 4]);
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            received = backend.take_received(&listener);
+            if !received.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(received, vec![vec![1, 2, 3, 4]]);
+    }
+
+    #[tokio::test]
+    async fn killed_bots_are_reported_as_dead() {
+        let backend = MockBackend::new();
+        let track = PubIdentify("Track1".to_owned());
+        let listener = SubIdentify("Listener".to_owned());
+        let (dead_tx, mut dead_rx) = mpsc::unbounded_channel();
+        backend
+            .start_source(
+                track.clone(),
+                GuildId::new(1),
+                "mock",
+                dead_tx.clone(),
+                mpsc::unbounded_channel().0,
+                status(BotRole::Speaker, "Track1"),
+            )
+            .await
+            .unwrap();
+        backend
+            .start_sink(
+                listener.clone(),
+                GuildId::new(1),
+                "mock",
+                dead_tx,
+                status(BotRole::Listener, "Listener"),
+            )
+            .await
+            .unwrap();
+
+        backend.kill_source(&track);
+        assert!(matches!(dead_rx.recv().await, Some(DeadBot::Speaker(t)) if t == track));
+        backend.kill_sink(&listener);
+        assert!(matches!(dead_rx.recv().await, Some(DeadBot::Listener(l)) if l == listener));
+    }
+
+    #[tokio::test]
+    async fn member_comes_from_set_user_name() {
+        let backend = MockBackend::new();
+        backend.set_user_name(UserId::new(42), "Alice");
+        let member = backend
+            .member(GuildId::new(1), UserId::new(42))
+            .await
+            .unwrap();
+        assert_eq!(member.display_name, "Alice");
+        assert!(backend
+            .member(GuildId::new(1), UserId::new(43))
+            .await
+            .is_err());
+    }
+
+    // 受け取ったイベントを記録する
+    #[derive(Default)]
+    struct RecordingEmitter {
+        events: Mutex<Vec<(String, Value)>>,
+    }
+
+    impl EventEmitter for RecordingEmitter {
+        fn emit(&self, event: &str, payload: Value) {
+            let mut events = self.events.lock().unwrap();
+            events.push((event.to_owned(), payload));
+        }
+    }
+
+    impl RecordingEmitter {
+        fn user_events(&self) -> Vec<Value> {
+            let events = self.events.lock().unwrap();
+            events
+                .iter()
+                .filter(|(event, _)| event == "user-data-changed")
+                .map(|(_, payload)| payload.clone())
+                .collect()
+        }
+    }
+
+    // MockBackendの上でVCを動かす
+    struct Harness {
+        vc: VC,
+        backend: Arc<MockBackend>,
+        emitter: Arc<RecordingEmitter>,
+        track: PubIdentify,
+        listener: SubIdentify,
+    }
+
+    async fn start() -> Harness {
+        let session: SessionConfig = serde_json::from_value(json!({
+            "id": "main",
+            "speakers": [{ "track": "Track1", "api": "mock" }],
+            "listeners": [{ "name": "Listener", "api": "mock" }],
+        }))
+        .unwrap();
+        let backend = Arc::new(MockBackend::new());
+        let emitter = Arc::new(RecordingEmitter::default());
+        let mut vc = VC::from_session(
+            &session,
+            GuildId::new(1),
+            Arc::new(Overlay::new()),
+            // failoverで使う予備Bot
+            Arc::new(BotPool::new(vec!["standby".to_owned()])),
+            backend.clone(),
+            emitter.clone(),
+        );
+        vc.start_bot(&session.speakers, &session.listeners)
+            .await
+            .unwrap();
+        Harness {
+            vc,
+            backend,
+            emitter,
+            track: PubIdentify("Track1".to_owned()),
+            listener: SubIdentify("Listener".to_owned()),
+        }
+    }
+
+    impl Harness {
+        async fn join(&mut self) {
+            let speakers = HashMap::from([(self.track.clone(), ChannelId::new(10))]);
+            let listeners = HashMap::from([(self.listener.clone(), ChannelId::new(20))]);
+            self.vc.join(speakers, listeners).await.unwrap();
+        }
+    }
+
+    // VoiceManagerやlistenerのtaskが処理するまで待つ
+    async fn eventually(mut check: impl FnMut() -> bool) -> bool {
+        for _ in 0..100 {
+            if check() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    // failoverやanchorの追従を動かす
+    fn supervise(vc: VC) -> Arc<tokio::sync::Mutex<VC>> {
+        let vc = Arc::new(tokio::sync::Mutex::new(vc));
+        tokio::spawn(VC::supervise(vc.clone()));
+        vc
+    }
+
+    fn pcm_f32(data: &[u8]) -> Vec<f32> {
+        data.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn join_and_leave() {
+        let mut h = start().await;
+        h.join().await;
+        assert_eq!(h.backend.source_channel(&h.track), Some(ChannelId::new(10)));
+        assert_eq!(
+            h.backend.sink_channel(&h.listener),
+            Some(ChannelId::new(20))
+        );
+
+        h.vc.leave().await;
+        assert_eq!(h.backend.source_channel(&h.track), None);
+        assert_eq!(h.backend.sink_channel(&h.listener), None);
+    }
+
+    #[tokio::test]
+    async fn user_join_and_leave_are_emitted() {
+        let mut h = start().await;
+        h.backend.set_user_name(UserId::new(42), "Alice");
+        h.join().await;
+
+        h.backend
+            .user_join(&h.track, VoiceUserId(42))
+            .await
+            .unwrap();
+        // 名前は取得できてからUpdateで送られる
+        assert!(eventually(|| h.emitter.user_events().len() == 2).await);
+        let joined = &h.emitter.user_events()[0];
+        assert_eq!(joined["event"], "Join");
+        assert_eq!(joined["name"], "42");
+        assert_eq!(joined["user_id"], "42");
+        assert_eq!(joined["identify"], "Track1");
+        let updated = &h.emitter.user_events()[1];
+        assert_eq!(updated["event"], "Update");
+        assert_eq!(updated["name"], "Alice");
+
+        h.backend
+            .user_leave(&h.track, VoiceUserId(42))
+            .await
+            .unwrap();
+        assert!(eventually(|| h.emitter.user_events().len() == 3).await);
+        assert_eq!(h.emitter.user_events()[2]["event"], "Leave");
+        assert_eq!(h.emitter.user_events()[2]["name"], "Alice");
+    }
+
+    #[tokio::test]
+    async fn slow_member_lookup_does_not_stall_voice() {
+        let mut h = start().await;
+        h.backend.set_user_name(UserId::new(42), "Alice");
+        h.backend.set_member_delay(Duration::from_secs(5));
+        h.join().await;
+        h.vc.update_is_listening(h.track.clone(), true)
+            .await
+            .unwrap();
+
+        h.backend
+            .user_join(&h.track, VoiceUserId(42))
+            .await
+            .unwrap();
+        h.backend
+            .speak(&h.track, VoiceUserId(42), vec![16384; 4])
+            .await
+            .unwrap();
+        assert!(eventually(|| !h.backend.take_received(&h.listener).is_empty()).await);
+        assert_eq!(h.emitter.user_events().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_member_lookup_is_not_retried_at_once() {
+        let mut h = start().await;
+        h.join().await;
+
+        for _ in 0..3 {
+            h.backend
+                .user_join(&h.track, VoiceUserId(42))
+                .await
+                .unwrap();
+            h.backend
+                .user_leave(&h.track, VoiceUserId(42))
+                .await
+                .unwrap();
+        }
+        assert!(eventually(|| h.emitter.user_events().len() == 6).await);
+        assert!(eventually(|| h.backend.member_calls() == 1).await);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(h.backend.member_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn voice_is_relayed_only_while_listening() {
+        let mut h = start().await;
+        h.join().await;
+        h.backend
+            .user_join(&h.track, VoiceUserId(42))
+            .await
+            .unwrap();
+
+        h.backend
+            .speak(&h.track, VoiceUserId(42), vec![16384; 4])
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(h.backend.take_received(&h.listener).is_empty());
+
+        h.vc.update_is_listening(h.track.clone(), true)
+            .await
+            .unwrap();
+        h.backend
+            .speak(&h.track, VoiceUserId(42), vec![16384; 4])
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        assert!(
+            eventually(|| {
+                received.extend(h.backend.take_received(&h.listener));
+                !received.is_empty()
+            })
+            .await
+        );
+        assert_eq!(pcm_f32(&received[0]), vec![0.5; 4]);
+    }
+
+    #[tokio::test]
+    async fn volume_is_applied_per_track() {
+        let mut h = start().await;
+        h.join().await;
+        h.vc.update_is_listening(h.track.clone(), true)
+            .await
+            .unwrap();
+        h.vc.update_volume(Some(&h.track), UserId::new(42), 2.)
+            .await;
+
+        h.backend
+            .speak(&h.track, VoiceUserId(42), vec![8192; 4])
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        assert!(
+            eventually(|| {
+                received.extend(h.backend.take_received(&h.listener));
+                !received.is_empty()
+            })
+            .await
+        );
+        assert_eq!(pcm_f32(&received[0]), vec![0.5; 4]);
+    }
+
+    #[tokio::test]
+    async fn standby_bot_takes_over_a_dead_speaker() {
+        let mut h = start().await;
+        h.join().await;
+        h.vc.update_is_listening(h.track.clone(), true)
+            .await
+            .unwrap();
+        let Harness {
+            vc,
+            backend,
+            emitter,
+            track,
+            listener,
+        } = h;
+        let vc = supervise(vc);
+
+        backend.kill_source(&track);
+        // 予備Botが同じチャンネルに入るまで待つ
+        assert!(
+            eventually(|| vc.try_lock().is_ok_and(|vc| vc.get_status().iter().any(
+                |status| status.role == BotRole::Speaker && status.state == BotState::InChannel
+            )))
+            .await
+        );
+        assert_eq!(backend.source_channel(&track), Some(ChannelId::new(10)));
+        let stopped = json!({
+            "session": "main",
+            "role": "speaker",
+            "name": "Track1",
+            "state": "stopped",
+        });
+        assert!(emitter
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(event, payload)| event == "bot-status-changed" && *payload == stopped));
+
+        // 止まったBotは退出しているので音声は1回だけ流れる
+        backend.user_join(&track, VoiceUserId(42)).await.unwrap();
+        backend
+            .speak(&track, VoiceUserId(42), vec![16384; 4])
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        assert!(
+            eventually(|| {
+                received.extend(backend.take_received(&listener));
+                !received.is_empty()
+            })
+            .await
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        received.extend(backend.take_received(&listener));
+        assert_eq!(received.len(), 1);
+        assert_eq!(pcm_f32(&received[0]), vec![0.5; 4]);
+    }
+
+    #[tokio::test]
+    async fn failed_switch_keeps_the_original_channel() {
+        let mut h = start().await;
+        h.join().await;
+        h.backend.set_unjoinable(ChannelId::new(11));
+
+        assert!(h
+            .vc
+            .switch_channel(BotRole::Speaker, "Track1", ChannelId::new(11))
+            .await
+            .is_err());
+        assert_eq!(h.backend.source_channel(&h.track), Some(ChannelId::new(10)));
+        // VCも元のチャンネルにいるものとして扱う(同じチャンネルのpresetでは移動しない)
+        let preset = MatchPreset {
+            speakers: HashMap::from([(h.track.clone(), ChannelId::new(10))]),
+            ..Default::default()
+        };
+        h.vc.apply_preset(&preset).await.unwrap();
+        assert_eq!(h.backend.source_channel(&h.track), Some(ChannelId::new(10)));
+
+        h.vc.switch_channel(BotRole::Speaker, "Track1", ChannelId::new(12))
+            .await
+            .unwrap();
+        assert_eq!(h.backend.source_channel(&h.track), Some(ChannelId::new(12)));
+    }
+
+    #[tokio::test]
+    async fn speaker_follows_the_anchor() {
+        let mut h = start().await;
+        h.join().await;
+        h.vc.set_anchor(h.track.clone(), Some(UserId::new(7)))
+            .unwrap();
+        let _vc = supervise(h.vc);
+
+        h.backend
+            .move_user(UserId::new(7), Some(ChannelId::new(11)));
+        assert!(
+            eventually(|| h.backend.source_channel(&h.track) == Some(ChannelId::new(11))).await
+        );
+        let followed = json!({ "session": "main", "track": "Track1", "channel_id": "11" });
+        assert!(
+            eventually(|| h
+                .emitter
+                .events
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|(event, payload)| event == "speaker-followed" && *payload == followed))
+            .await
+        );
+        // 他のユーザーが移っても追従しない
+        h.backend
+            .move_user(UserId::new(8), Some(ChannelId::new(12)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(h.backend.source_channel(&h.track), Some(ChannelId::new(11)));
+    }
+
+    #[tokio::test]
+    async fn speaker_stays_when_the_anchor_disconnects() {
+        let mut h = start().await;
+        h.join().await;
+        h.vc.set_anchor(h.track.clone(), Some(UserId::new(7)))
+            .unwrap();
+        let _vc = supervise(h.vc);
+
+        h.backend.move_user(UserId::new(7), None);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(h.backend.source_channel(&h.track), Some(ChannelId::new(10)));
+        assert!(!h
+            .emitter
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(event, _)| event == "speaker-followed"));
+    }
+
+    #[tokio::test]
+    async fn users_already_in_the_channel_are_emitted_on_join() {
+        let mut h = start().await;
+        h.backend.set_user_name(UserId::new(42), "Alice");
+        h.backend
+            .set_present(ChannelId::new(10), vec![UserId::new(42)]);
+        h.backend
+            .set_present(ChannelId::new(11), vec![UserId::new(43)]);
+        h.join().await;
+
+        // 誰も話していなくてもJoinが届く
+        assert!(
+            eventually(|| h
+                .emitter
+                .user_events()
+                .iter()
+                .any(|e| e["user_id"] == "42" && e["event"] == "Join"))
+            .await
+        );
+
+        // 移った先のユーザーはJoin，元のチャンネルのユーザーはLeave
+        h.vc.switch_channel(BotRole::Speaker, "Track1", ChannelId::new(11))
+            .await
+            .unwrap();
+        assert!(
+            eventually(|| {
+                let events = h.emitter.user_events();
+                events
+                    .iter()
+                    .any(|e| e["user_id"] == "43" && e["event"] == "Join")
+                    && events
+                        .iter()
+                        .any(|e| e["user_id"] == "42" && e["event"] == "Leave")
+            })
+            .await
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::State,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html,
+    },
+    routing::get,
+    Router,
+};
+use log::{error, info};
+use serde::Serialize;
+use songbird::model::id::UserId as VoiceUserId;
+use tokio::sync::{broadcast, RwLock};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use super::types::{PubIdentify, SessionId};
+
+const OVERLAY_HTML: &str = include_str!("overlay.html");
+
+#[derive(Serialize, Clone, Debug)]
+pub struct OverlayUser {
+    pub session: SessionId,
+    pub user_id: VoiceUserId,
+    pub name: String,
+    pub avatar_url: Option<String>,
+    pub identify: PubIdentify,
+    pub speaking: bool,
+}
+
+type OverlayUsers = HashMap<(SessionId, VoiceUserId), OverlayUser>;
+
+// OBSのブラウザソース向けに話者の状態を配信する
+// 全sessionで1つのサーバーを共有する
+pub struct Overlay {
+    users: RwLock<OverlayUsers>,
+    tx: broadcast::Sender<String>,
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(64);
+        Overlay {
+            users: RwLock::new(HashMap::new()),
+            tx,
+        }
+    }
+    pub async fn join_user(
+        &self,
+        session: &SessionId,
+        user_id: VoiceUserId,
+        name: String,
+        avatar_url: Option<String>,
+        identify: PubIdentify,
+    ) {
+        let mut users = self.users.write().await;
+        let key = (session.clone(), user_id);
+        let speaking = users.get(&key).is_some_and(|u| u.speaking);
+        users.insert(
+            key,
+            OverlayUser {
+                session: session.clone(),
+                user_id,
+                name,
+                avatar_url,
+                identify,
+                speaking,
+            },
+        );
+        self.publish(&users);
+    }
+    pub async fn leave_user(&self, session: &SessionId, user_id: VoiceUserId) {
+        let mut users = self.users.write().await;
+        if users.remove(&(session.clone(), user_id)).is_some() {
+            self.publish(&users);
+        }
+    }
+    pub async fn set_speaking(&self, session: &SessionId, user_id: VoiceUserId, speaking: bool) {
+        let mut users = self.users.write().await;
+        // Join前の発話は名前が分からないので無視
+        if let Some(user) = users.get_mut(&(session.clone(), user_id)) {
+            if user.speaking != speaking {
+                user.speaking = speaking;
+                self.publish(&users);
+            }
+        }
+    }
+    pub async fn clear(&self, session: &SessionId) {
+        let mut users = self.users.write().await;
+        users.retain(|(user_session, _), _| user_session != session);
+        self.publish(&users);
+    }
+    fn snapshot(users: &OverlayUsers) -> String {
+        let mut list: Vec<&OverlayUser> = users.values().collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        serde_json::to_string(&list).unwrap_or_else(|_| "[]".to_string())
+    }
+    fn publish(&self, users: &OverlayUsers) {
+        // 購読者がいない場合はErrになるが問題ない
+        let _ = self.tx.send(Self::snapshot(users));
+    }
+    pub async fn serve(self: Arc<Self>, port: u16) {
+        let app = Router::new()
+            .route("/", get(index))
+            .route("/events", get(events))
+            .with_state(self);
+        let listener = match tokio::net::TcpListener::bind(("127.0.0.1", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("failed to bind overlay server on port {}: {:?}", port, e);
+                return;
+            }
+        };
+        info!("overlay server listening on http://127.0.0.1:{}", port);
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("overlay server error: {:?}", e);
+        }
+    }
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn index() -> Html<&'static str> {
+    Html(OVERLAY_HTML)
+}
+
+async fn events(
+    State(overlay): State<Arc<Overlay>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // snapshotより先にsubscribeして取りこぼしを防ぐ
+    let rx = overlay.tx.subscribe();
+    let snapshot = {
+        let users = overlay.users.read().await;
+        Overlay::snapshot(&users)
+    };
+    let updates = BroadcastStream::new(rx).filter_map(|data| data.ok());
+    let stream = tokio_stream::once(snapshot)
+        .chain(updates)
+        .map(|data| Ok(Event::default().event("users").data(data)));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,209 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::{info, warn};
+use serenity::{
+    all::{ChannelId, Context, VoiceState},
+    async_trait,
+};
+use songbird::{
+    error::JoinError, Call, CoreEvent, Event, EventContext, EventHandler as VoiceEventHandler,
+    Songbird,
+};
+use tokio::sync::RwLock;
+
+use super::{
+    join_session::TaskScope,
+    status::{BotState, StatusReporter},
+    types::JoinInfo,
+};
+
+const REJOIN_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const REJOIN_MAX_DELAY: Duration = Duration::from_secs(30);
+
+// Join中のReconnector(leaveでNoneにする)
+pub type ReconnectorSlot = Arc<RwLock<Option<Reconnector>>>;
+// Callにhandlerを登録する(再接続時にも呼ぶ)
+pub type SetupCallFn = Arc<dyn Fn(&mut Call) + Send + Sync>;
+
+// 意図せずVCから切断されたときに，Joinしたチャンネルへbackoffしながら入り直す
+#[derive(Clone)]
+pub struct Reconnector {
+    name: String,
+    manager: Arc<Songbird>,
+    join_info: JoinInfo,
+    setup_call: SetupCallFn,
+    status: StatusReporter,
+    // 再接続taskはJoin中のtaskとして起動する
+    tasks: TaskScope,
+    // leave後はfalse
+    active: Arc<AtomicBool>,
+    rejoining: Arc<AtomicBool>,
+}
+
+impl Reconnector {
+    pub fn new(
+        name: String,
+        manager: Arc<Songbird>,
+        join_info: JoinInfo,
+        setup_call: SetupCallFn,
+        status: StatusReporter,
+        tasks: TaskScope,
+    ) -> Self {
+        Reconnector {
+            name,
+            manager,
+            join_info,
+            setup_call,
+            status,
+            tasks,
+            active: Arc::new(AtomicBool::new(true)),
+            rejoining: Arc::new(AtomicBool::new(false)),
+        }
+    }
+    // handlerと切断検知のEventを登録する
+    pub fn setup(&self, call: &mut Call) {
+        (self.setup_call)(call);
+        call.add_global_event(CoreEvent::DriverDisconnect.into(), self.clone());
+        call.add_global_event(CoreEvent::DriverReconnect.into(), self.clone());
+    }
+    pub fn channel_id(&self) -> ChannelId {
+        self.join_info.channel_id
+    }
+    pub fn stop(&self) {
+        self.active.store(false, Ordering::SeqCst);
+    }
+    // 同じhandlerのまま入るチャンネルだけ変える
+    pub fn retarget(&self, channel_id: ChannelId) -> Reconnector {
+        Reconnector {
+            join_info: JoinInfo {
+                channel_id,
+                ..self.join_info
+            },
+            active: Arc::new(AtomicBool::new(true)),
+            rejoining: Arc::new(AtomicBool::new(false)),
+            ..self.clone()
+        }
+    }
+    // join_infoのチャンネルに入る(既存のCallはそのまま使う)
+    pub async fn join(&self) -> Result<(), JoinError> {
+        let JoinInfo {
+            guild_id,
+            channel_id,
+        } = self.join_info;
+        // Callが作り直されている場合もあるのでhandlerを登録し直す
+        {
+            let handler_lock = self.manager.get_or_insert(guild_id);
+            let mut handler = handler_lock.lock().await;
+            handler.remove_all_global_events();
+            self.setup(&mut handler);
+        }
+        self.manager.join(guild_id, channel_id).await?;
+        self.status.set(BotState::InChannel);
+        Ok(())
+    }
+    fn trigger(&self) {
+        if !self.active.load(Ordering::SeqCst) || self.rejoining.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.status.set(BotState::Reconnecting);
+        let reconnector = self.clone();
+        self.tasks.spawn(async move {
+            reconnector.rejoin().await;
+            reconnector.rejoining.store(false, Ordering::SeqCst);
+        });
+    }
+    async fn rejoin(&self) {
+        let channel_id = self.join_info.channel_id;
+        let mut delay = REJOIN_INITIAL_DELAY;
+        while self.active.load(Ordering::SeqCst) {
+            tokio::time::sleep(delay).await;
+            if !self.active.load(Ordering::SeqCst) {
+                break;
+            }
+            match self.join().await {
+                Ok(_) => {
+                    info!("{} rejoined {}", self.name, channel_id);
+                    return;
+                }
+                Err(e) => {
+                    warn!("{} failed to rejoin {}: {:?}", self.name, channel_id, e);
+                    delay = (delay * 2).min(REJOIN_MAX_DELAY);
+                }
+            }
+        }
+    }
+    // Join中のBotを別のチャンネルに移す(再接続先も変える)
+    pub async fn switch_channel(
+        slot: &ReconnectorSlot,
+        channel_id: ChannelId,
+    ) -> Option<Result<(), JoinError>> {
+        // 移動中にvoice_state_updateで元のチャンネルに戻さないようにロックしておく
+        let mut slot = slot.write().await;
+        let old = slot.take()?;
+        old.stop();
+        let reconnector = old.retarget(channel_id);
+        let res = reconnector.join().await;
+        if res.is_err() {
+            // 移れなかった場合は元のチャンネルに入り直す(再接続先も元に戻す)
+            reconnector.stop();
+            let restored = old.retarget(old.channel_id());
+            if let Err(e) = restored.join().await {
+                warn!(
+                    "{} failed to return to {}: {:?}",
+                    old.name,
+                    old.channel_id(),
+                    e
+                );
+                restored.trigger();
+            }
+            *slot = Some(restored);
+            return Some(res);
+        }
+        *slot = Some(reconnector);
+        Some(res)
+    }
+    // Botが別のVCに移動させられた，または切断された場合
+    pub async fn on_voice_state_update(slot: &ReconnectorSlot, ctx: &Context, state: &VoiceState) {
+        if state.user_id != ctx.cache.current_user().id {
+            return;
+        }
+        let reconnector = match slot.read().await.clone() {
+            Some(reconnector) => reconnector,
+            None => return,
+        };
+        if state.guild_id != Some(reconnector.join_info.guild_id) {
+            return;
+        }
+        if state.channel_id != Some(reconnector.join_info.channel_id) {
+            warn!(
+                "{} left {} unexpectedly",
+                reconnector.name, reconnector.join_info.channel_id
+            );
+            reconnector.trigger();
+        }
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for Reconnector {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        match ctx {
+            EventContext::DriverDisconnect(disconnect) => {
+                warn!("{} driver disconnected: {:?}", self.name, disconnect.reason);
+                self.trigger();
+            }
+            EventContext::DriverReconnect(_) => {
+                info!("{} driver reconnected", self.name);
+                self.status.set(BotState::InChannel);
+            }
+            _ => {}
+        }
+        None
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use super::{
+    error::VcError,
+    types::{PubIdentify, SessionId, SubIdentify},
+};
+
+const fn default_capacity() -> usize {
+    256
+}
+
+// listenerの再生が遅れて音声が溜まったときの方針
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum BackpressurePolicy {
+    // capacityを超えたら古い音声から捨てる
+    DropOldest {
+        #[serde(default = "default_capacity")]
+        capacity: usize,
+    },
+    // 遅延がmax_latency_msを超えた音声を捨てる
+    MaxLatency {
+        max_latency_ms: u64,
+    },
+    // 遅延がthreshold_msを超えたら音声を飛ばしてrate倍の速さで追いつく(max_latency_msを超えたら捨てる)
+    // 音声自体は速くしないので声の高さは変わらない
+    CatchUp {
+        threshold_ms: u64,
+        rate: f32,
+        max_latency_ms: u64,
+    },
+}
+
+impl Default for BackpressurePolicy {
+    fn default() -> Self {
+        BackpressurePolicy::DropOldest {
+            capacity: default_capacity(),
+        }
+    }
+}
+
+impl BackpressurePolicy {
+    // rateが1以下だと追いつかないので設定の誤りにする
+    pub fn validate(&self, session: &SessionId) -> Result<(), VcError> {
+        match self {
+            BackpressurePolicy::CatchUp { rate, .. } if rate.is_nan() || *rate <= 1. => {
+                Err(VcError::Config(format!(
+                    "{}: catch_up rate must be greater than 1.0 (got {})",
+                    session, rate
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// failoverで送信先を作り直しても引き継ぐ
+#[derive(Default)]
+pub struct RelayMetrics {
+    dropped_frames: AtomicU64,
+    skipped_frames: AtomicU64,
+}
+
+impl RelayMetrics {
+    pub fn count_dropped(&self, frames: u64) {
+        self.dropped_frames.fetch_add(frames, Ordering::Relaxed);
+    }
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames.load(Ordering::Relaxed)
+    }
+}
+
+// 選手VC側で，VoiceManagerが詰まっていて渡せずに捨てた音声
+#[derive(Serialize, Clone, Debug)]
+pub struct TrackStats {
+    pub track: PubIdentify,
+    pub dropped_frames: u64,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RelayReport {
+    pub tracks: Vec<TrackStats>,
+    pub listeners: Vec<RelayStats>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RelayStats {
+    pub listener: SubIdentify,
+    pub queued_frames: usize,
+    // 一番古い音声が待っている時間
+    pub latency_ms: u64,
+    pub dropped_frames: u64,
+    // catch_upで追いつくために飛ばした音声
+    pub skipped_frames: u64,
+}
+
+struct Frame {
+    pcm: Vec<u8>,
+    queued_at: Instant,
+}
+
+struct Shared {
+    policy: BackpressurePolicy,
+    queue: Mutex<VecDeque<Frame>>,
+    notify: Notify,
+    closed: AtomicBool,
+    metrics: Arc<RelayMetrics>,
+}
+
+impl Shared {
+    // policyを超えた分を捨てる
+    fn trim(&self, queue: &mut VecDeque<Frame>) {
+        let dropped = match self.policy {
+            BackpressurePolicy::DropOldest { capacity } => {
+                let over = queue.len().saturating_sub(capacity.max(1));
+                queue.drain(..over);
+                over
+            }
+            BackpressurePolicy::MaxLatency { max_latency_ms }
+            | BackpressurePolicy::CatchUp { max_latency_ms, .. } => {
+                let max_latency = Duration::from_millis(max_latency_ms);
+                let mut over = 0;
+                while queue
+                    .front()
+                    .is_some_and(|frame| frame.queued_at.elapsed() > max_latency)
+                {
+                    queue.pop_front();
+                    over += 1;
+                }
+                over
+            }
+        };
+        if dropped > 0 {
+            self.metrics.count_dropped(dropped as u64);
+            debug!("dropped {} frames", dropped);
+        }
+    }
+    // 遅れている間は1-1/rateの割合で音声をまるごと飛ばす
+    // (サンプルを間引くと声が高くなるので，packetごと捨てる)
+    fn catch_up(&self, frame: &Frame, skip: &mut f32) -> bool {
+        if let BackpressurePolicy::CatchUp {
+            threshold_ms, rate, ..
+        } = self.policy
+        {
+            if frame.queued_at.elapsed() > Duration::from_millis(threshold_ms) {
+                *skip += 1. - 1. / rate;
+                if *skip >= 1. {
+                    *skip -= 1.;
+                    self.metrics.skipped_frames.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+                return false;
+            }
+        }
+        *skip = 0.;
+        false
+    }
+}
+
+// 送信側が全てdropされたら受信側を終わらせる
+struct Closer(Arc<Shared>);
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::SeqCst);
+        self.0.notify.notify_one();
+    }
+}
+
+// VoiceManagerからlistenerへの送信側．送信で待たない
+#[derive(Clone)]
+pub struct RelaySender {
+    shared: Arc<Shared>,
+    _closer: Arc<Closer>,
+}
+
+pub struct RelayReceiver {
+    shared: Arc<Shared>,
+    // catch_upで飛ばす分
+    skip: f32,
+}
+
+pub fn relay_channel(
+    policy: BackpressurePolicy,
+    metrics: Arc<RelayMetrics>,
+) -> (RelaySender, RelayReceiver) {
+    let shared = Arc::new(Shared {
+        policy,
+        queue: Mutex::new(VecDeque::new()),
+        notify: Notify::new(),
+        closed: AtomicBool::new(false),
+        metrics,
+    });
+    let tx = RelaySender {
+        shared: shared.clone(),
+        _closer: Arc::new(Closer(shared.clone())),
+    };
+    (tx, RelayReceiver { shared, skip: 0. })
+}
+
+impl RelaySender {
+    pub fn send(&self, pcm: Vec<u8>) {
+        {
+            let mut queue = self.shared.queue.lock().unwrap();
+            queue.push_back(Frame {
+                pcm,
+                queued_at: Instant::now(),
+            });
+            self.shared.trim(&mut queue);
+        }
+        self.shared.notify.notify_one();
+    }
+    pub fn metrics(&self) -> Arc<RelayMetrics> {
+        self.shared.metrics.clone()
+    }
+    pub fn stats(&self, listener: SubIdentify) -> RelayStats {
+        let queue = self.shared.queue.lock().unwrap();
+        let metrics = &self.shared.metrics;
+        RelayStats {
+            listener,
+            queued_frames: queue.len(),
+            latency_ms: queue
+                .front()
+                .map_or(0, |frame| frame.queued_at.elapsed().as_millis() as u64),
+            dropped_frames: metrics.dropped_frames(),
+            skipped_frames: metrics.skipped_frames.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl RelayReceiver {
+    // 送信側が全てdropされて空になったらNone
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let notified = self.shared.notify.notified();
+            {
+                let mut queue = self.shared.queue.lock().unwrap();
+                self.shared.trim(&mut queue);
+                while let Some(frame) = queue.pop_front() {
+                    if !self.shared.catch_up(&frame, &mut self.skip) {
+                        return Some(frame.pcm);
+                    }
+                }
+                if self.shared.closed.load(Ordering::SeqCst) {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn recv_all(mut rx: RelayReceiver) -> Vec<Vec<u8>> {
+        let mut received = Vec::new();
+        while let Some(pcm) = rx.recv().await {
+            received.push(pcm);
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_capacity_frames() {
+        let (tx, rx) = relay_channel(
+            BackpressurePolicy::DropOldest { capacity: 3 },
+            Default::default(),
+        );
+        for i in 0..5 {
+            tx.send(vec![i]);
+        }
+        let stats = tx.stats(SubIdentify("Listener".to_owned()));
+        assert_eq!(stats.queued_frames, 3);
+        assert_eq!(stats.dropped_frames, 2);
+        drop(tx);
+        assert_eq!(recv_all(rx).await, vec![vec![2], vec![3], vec![4]]);
+    }
+
+    #[tokio::test]
+    async fn max_latency_drops_expired_frames() {
+        let (tx, rx) = relay_channel(
+            BackpressurePolicy::MaxLatency { max_latency_ms: 20 },
+            Default::default(),
+        );
+        tx.send(vec![0]);
+        tx.send(vec![1]);
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        tx.send(vec![2]);
+        let metrics = tx.metrics();
+        drop(tx);
+        assert_eq!(recv_all(rx).await, vec![vec![2]]);
+        assert_eq!(metrics.dropped_frames(), 2);
+    }
+
+    #[tokio::test]
+    async fn catch_up_skips_one_minus_inverse_rate() {
+        for (rate, skipped) in [(2., 5), (4. / 3., 2)] {
+            let (tx, rx) = relay_channel(
+                BackpressurePolicy::CatchUp {
+                    threshold_ms: 0,
+                    rate,
+                    max_latency_ms: 10_000,
+                },
+                Default::default(),
+            );
+            for i in 0..10 {
+                tx.send(vec![i]);
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            let metrics = tx.metrics();
+            drop(tx);
+            assert_eq!(recv_all(rx).await.len(), 10 - skipped);
+            assert_eq!(
+                metrics.skipped_frames.load(Ordering::Relaxed),
+                skipped as u64
+            );
+            assert_eq!(metrics.dropped_frames(), 0);
+        }
+    }
+
+    #[test]
+    fn catch_up_rate_must_be_greater_than_one() {
+        let session = SessionId("main".to_owned());
+        let policy = |rate| BackpressurePolicy::CatchUp {
+            threshold_ms: 100,
+            rate,
+            max_latency_ms: 1000,
+        };
+        assert!(policy(1.25).validate(&session).is_ok());
+        assert!(policy(1.).validate(&session).is_err());
+        assert!(policy(0.5).validate(&session).is_err());
+        assert!(policy(f32::NAN).validate(&session).is_err());
+        assert!(BackpressurePolicy::default().validate(&session).is_ok());
+    }
+}
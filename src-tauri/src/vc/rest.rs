@@ -0,0 +1,145 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use log::warn;
+use serenity::{
+    http::{Http, HttpError, StatusCode},
+    Error as SerenityError,
+};
+use tokio::sync::Semaphore;
+
+use super::error::VcError;
+
+// 一時的なエラーの再試行回数と待ち時間(1回ごとに倍にする)
+const MAX_RETRIES: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+// 1つのBotから同時に投げるリクエストの数(多人数が一度にJoinしても詰め込まない)
+const MAX_CONCURRENT: usize = 4;
+
+// Discord REST APIの呼び出し口(Botごとに1つ)
+// X-RateLimit-*ヘッダーに従った待機はserenityのRatelimiterに任せ，
+// ここでは同時リクエスト数の制限と，429/5xx/通信エラーの再試行をする
+#[derive(Clone)]
+pub struct Rest {
+    http: Arc<Http>,
+    permits: Arc<Semaphore>,
+}
+
+impl Rest {
+    pub fn new(http: Arc<Http>) -> Self {
+        Rest {
+            http,
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT)),
+        }
+    }
+    // whatはログ用の名前
+    pub async fn call<T, F, Fut>(&self, what: &str, f: F) -> Result<T, VcError>
+    where
+        F: Fn(Arc<Http>) -> Fut,
+        Fut: Future<Output = Result<T, SerenityError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = {
+                // Semaphoreは閉じないのでErrにはならない
+                let _permit = self.permits.acquire().await.ok();
+                f(self.http.clone()).await
+            };
+            match result {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < MAX_RETRIES && is_transient(&e) => {
+                    let delay = RETRY_DELAY * 2u32.pow(attempt);
+                    warn!("{} failed: {}, retry in {:?}", what, e, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+// 待てば通る可能性のあるエラー(Tokenや権限のエラーは再試行しない)
+fn is_transient(e: &SerenityError) -> bool {
+    match e {
+        SerenityError::Http(HttpError::UnsuccessfulRequest(res)) => {
+            is_transient_status(res.status_code)
+        }
+        SerenityError::Http(HttpError::Request(e)) => e.is_timeout() || e.is_connect(),
+        _ => false,
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use serenity::http::HttpBuilder;
+
+    use super::*;
+
+    // 誰も待ち受けていないポートに送らせて通信エラーを起こす
+    fn unreachable_http() -> Arc<Http> {
+        Arc::new(
+            HttpBuilder::new("token")
+                .proxy("http://127.0.0.1:1")
+                .ratelimiter_disabled(true)
+                .build(),
+        )
+    }
+
+    // fが呼ばれた回数を返す
+    async fn count_calls(transient: bool) -> (Result<(), VcError>, u32) {
+        let rest = Rest::new(unreachable_http());
+        let calls = AtomicU32::new(0);
+        let res = rest
+            .call("test", |http| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if transient {
+                        http.get_current_user().await.map(|_| ())
+                    } else {
+                        Err(SerenityError::Other("invalid token"))
+                    }
+                }
+            })
+            .await;
+        (res, calls.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn rate_limits_and_server_errors_are_transient() {
+        assert!(is_transient_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_transient_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(is_transient_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_transient_status(StatusCode::BAD_REQUEST));
+        assert!(!is_transient_status(StatusCode::UNAUTHORIZED));
+        assert!(!is_transient_status(StatusCode::FORBIDDEN));
+        assert!(!is_transient_status(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn connect_errors_are_transient() {
+        let e = unreachable_http().get_current_user().await.unwrap_err();
+        assert!(matches!(e, SerenityError::Http(HttpError::Request(_))));
+        assert!(is_transient(&e));
+        assert!(!is_transient(&SerenityError::Other("invalid token")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_errors_are_retried_up_to_the_limit() {
+        let (res, calls) = count_calls(true).await;
+        assert!(res.is_err());
+        assert_eq!(calls, MAX_RETRIES + 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn other_errors_are_not_retried() {
+        let (res, calls) = count_calls(false).await;
+        assert!(res.is_err());
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,308 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::ChannelId;
+
+use super::{
+    bracket::{self, BracketKeys},
+    config::MatchPreset,
+    error::VcError,
+    types::{PubIdentify, SubIdentify},
+};
+
+// 大会スケジュールの1試合分
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduledMatch {
+    pub id: String,
+    #[serde(default)]
+    pub speakers: HashMap<PubIdentify, ChannelId>,
+    #[serde(default)]
+    pub listeners: HashMap<SubIdentify, ChannelId>,
+    // trackごとのチーム名
+    #[serde(default)]
+    pub teams: HashMap<PubIdentify, String>,
+}
+
+impl ScheduledMatch {
+    // チャンネルに入るtrackは全て聞き取る
+    pub fn to_preset(&self) -> MatchPreset {
+        MatchPreset {
+            name: self.id.clone(),
+            speakers: self.speakers.clone(),
+            listeners: self.listeners.clone(),
+            labels: self.teams.clone(),
+            listening: self.speakers.keys().cloned().collect(),
+        }
+    }
+}
+
+// 読み込んだスケジュールと今の試合
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Schedule {
+    pub matches: Vec<ScheduledMatch>,
+    pub current: Option<String>,
+}
+
+impl Schedule {
+    pub fn new(matches: Vec<ScheduledMatch>) -> Self {
+        Schedule {
+            matches,
+            current: None,
+        }
+    }
+    pub fn find(&self, match_id: &str) -> Option<&ScheduledMatch> {
+        self.matches.iter().find(|m| m.id == match_id)
+    }
+    // 今の試合の次(まだ始めていなければ最初の試合)
+    pub fn next(&self) -> Option<&ScheduledMatch> {
+        let index = match &self.current {
+            Some(current) => self.matches.iter().position(|m| m.id == *current)? + 1,
+            None => 0,
+        };
+        self.matches.get(index)
+    }
+}
+
+// 大会サイトが応答しなくてもImportが止まったままにならないように
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(super) fn client() -> Result<reqwest::Client, VcError> {
+    reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .map_err(|e| VcError::Schedule(e.to_string()))
+}
+
+// URL(http/https)かファイルパスからスケジュールを読み込む
+// URLはこの形式(JSONかCSV)を返す必要がある
+// start.gg/Challongeはbracket::loadで読む(チーム名はtracksの順に割り当てる)
+pub async fn load(
+    source: &str,
+    tracks: &[PubIdentify],
+    keys: &BracketKeys,
+) -> Result<Vec<ScheduledMatch>, VcError> {
+    if let Some(matches) = bracket::load(source, tracks, keys).await {
+        let matches = matches?;
+        if matches.is_empty() {
+            return Err(VcError::Schedule("no match in schedule".to_owned()));
+        }
+        return Ok(matches);
+    }
+    let text = if source.starts_with("http://") || source.starts_with("https://") {
+        let res = client()?
+            .get(source)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| VcError::Schedule(e.to_string()))?;
+        res.text()
+            .await
+            .map_err(|e| VcError::Schedule(e.to_string()))?
+    } else {
+        tokio::fs::read_to_string(source)
+            .await
+            .map_err(|e| VcError::Schedule(format!("{}: {}", source, e)))?
+    };
+    parse(&text)
+}
+
+// JSONかCSVかは中身で判断する
+pub fn parse(text: &str) -> Result<Vec<ScheduledMatch>, VcError> {
+    let matches = match text.trim_start().chars().next() {
+        Some('[') | Some('{') => parse_json(text)?,
+        _ => parse_csv(text)?,
+    };
+    if matches.is_empty() {
+        return Err(VcError::Schedule("no match in schedule".to_owned()));
+    }
+    Ok(matches)
+}
+
+// 試合の配列，または{ "matches": [...] }
+fn parse_json(text: &str) -> Result<Vec<ScheduledMatch>, VcError> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Json {
+        List(Vec<ScheduledMatch>),
+        Wrapped { matches: Vec<ScheduledMatch> },
+    }
+    let json: Json = serde_json::from_str(text).map_err(|e| VcError::Schedule(e.to_string()))?;
+    Ok(match json {
+        Json::List(matches) | Json::Wrapped { matches } => matches,
+    })
+}
+
+// 1行に1体のBot: match,role,name,channel_id,team
+// roleはspeaker(省略時)かlistener(大文字小文字は区別しない)．試合の順番は最初に出てきた順
+fn parse_csv(text: &str) -> Result<Vec<ScheduledMatch>, VcError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let (_, header) = lines
+        .next()
+        .ok_or_else(|| VcError::Schedule("empty schedule".to_owned()))?;
+    let header = split_csv_line(header);
+    let column = |name: &str| header.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let missing = |name: &str| VcError::Schedule(format!("missing column: {}", name));
+    let match_col = column("match").ok_or_else(|| missing("match"))?;
+    let name_col = column("name").ok_or_else(|| missing("name"))?;
+    let channel_col = column("channel_id").ok_or_else(|| missing("channel_id"))?;
+    let role_col = column("role");
+    let team_col = column("team");
+
+    let mut matches: Vec<ScheduledMatch> = Vec::new();
+    for (i, line) in lines {
+        let fields = split_csv_line(line);
+        let field = |col: Option<usize>| {
+            col.and_then(|col| fields.get(col))
+                .map(|f| f.as_str())
+                .unwrap_or("")
+        };
+        let match_id = field(Some(match_col));
+        let name = field(Some(name_col));
+        if match_id.is_empty() || name.is_empty() {
+            return Err(VcError::Schedule(format!(
+                "line {}: match and name are required",
+                i + 1
+            )));
+        }
+        let channel_id = match field(Some(channel_col)).parse::<u64>() {
+            Ok(id) if id != 0 => ChannelId::new(id),
+            _ => {
+                return Err(VcError::Schedule(format!(
+                    "line {}: invalid channel id",
+                    i + 1
+                )))
+            }
+        };
+        let index = match matches.iter().position(|m| m.id == match_id) {
+            Some(index) => index,
+            None => {
+                matches.push(ScheduledMatch {
+                    id: match_id.to_owned(),
+                    speakers: HashMap::new(),
+                    listeners: HashMap::new(),
+                    teams: HashMap::new(),
+                });
+                matches.len() - 1
+            }
+        };
+        let m = &mut matches[index];
+        match field(role_col).to_ascii_lowercase().as_str() {
+            "" | "speaker" => {
+                let track = PubIdentify(name.to_owned());
+                let team = field(team_col);
+                if !team.is_empty() {
+                    m.teams.insert(track.clone(), team.to_owned());
+                }
+                m.speakers.insert(track, channel_id);
+            }
+            "listener" => {
+                m.listeners.insert(SubIdentify(name.to_owned()), channel_id);
+            }
+            role => {
+                return Err(VcError::Schedule(format!(
+                    "line {}: unknown role {}",
+                    i + 1,
+                    role
+                )))
+            }
+        }
+    }
+    Ok(matches)
+}
+
+// ""で囲んだ値(中の,と"")に対応する
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_owned()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_owned());
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(name: &str) -> PubIdentify {
+        PubIdentify(name.to_owned())
+    }
+
+    #[test]
+    fn quoted_fields_keep_commas_and_quotes() {
+        assert_eq!(
+            split_csv_line(r#"A1, "Team, Alpha" ,"say ""hi""",,"#),
+            vec!["A1", "Team, Alpha", r#"say "hi""#, "", ""]
+        );
+    }
+
+    #[test]
+    fn csv_rows_are_grouped_by_match() {
+        let text = "match,role,name,channel_id,team
+A1,Speaker,Track1,101,\"Alpha, Inc\"
+A1,SPEAKER,Track2,102,
+A1,Listener,Caster,103,
+A2,,Track1,201,Gamma
+";
+        let matches = parse(text).unwrap();
+        assert_eq!(matches.len(), 2);
+        let a1 = &matches[0];
+        assert_eq!(a1.id, "A1");
+        assert_eq!(a1.speakers[&track("Track1")], ChannelId::new(101));
+        assert_eq!(a1.speakers[&track("Track2")], ChannelId::new(102));
+        assert_eq!(
+            a1.listeners[&SubIdentify("Caster".to_owned())],
+            ChannelId::new(103)
+        );
+        assert_eq!(a1.teams[&track("Track1")], "Alpha, Inc");
+        // 空のteamはラベルにしない
+        assert!(!a1.teams.contains_key(&track("Track2")));
+        // roleを省略するとspeaker
+        assert_eq!(matches[1].speakers[&track("Track1")], ChannelId::new(201));
+    }
+
+    #[test]
+    fn csv_without_role_column_is_all_speakers() {
+        let text = "match,name,channel_id\nA1,Track1,101\nA1,Track2,102\n";
+        let matches = parse(text).unwrap();
+        assert_eq!(matches[0].speakers.len(), 2);
+        assert!(matches[0].listeners.is_empty());
+    }
+
+    #[test]
+    fn csv_errors_are_reported() {
+        assert!(parse("match,name\nA1,Track1\n").is_err());
+        assert!(parse("match,name,channel_id\nA1,Track1,abc\n").is_err());
+        assert!(parse("match,role,name,channel_id\nA1,caster,Track1,101\n").is_err());
+        assert!(parse("match,name,channel_id\n").is_err());
+    }
+
+    #[test]
+    fn json_list_and_wrapped_are_read() {
+        let list = r#"[{"id": "A1", "speakers": {"Track1": "101"}, "teams": {"Track1": "Alpha"}}]"#;
+        let wrapped = r#"{"matches": [{"id": "A1", "speakers": {"Track1": "101"}, "teams": {"Track1": "Alpha"}}]}"#;
+        for text in [list, wrapped] {
+            let matches = parse(text).unwrap();
+            assert_eq!(matches.len(), 1);
+            assert_eq!(matches[0].id, "A1");
+            assert_eq!(matches[0].speakers[&track("Track1")], ChannelId::new(101));
+            assert_eq!(matches[0].teams[&track("Track1")], "Alpha");
+            assert!(matches[0].listeners.is_empty());
+        }
+        assert!(parse("[]").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::{events::EventEmitter, types::SessionId};
+
+// gatewayとVCの接続状態
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BotState {
+    Connecting,
+    Ready,
+    InChannel,
+    Reconnecting,
+    Failed,
+    // 終了時にgatewayを閉じた
+    Stopped,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BotRole {
+    Speaker,
+    Listener,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BotStatus {
+    pub session: SessionId,
+    pub role: BotRole,
+    // trackまたはlistenerの名前
+    pub name: String,
+    pub state: BotState,
+}
+
+// Bot1体分の接続状態．変化したらbot-status-changedを送る
+#[derive(Clone)]
+pub struct StatusReporter {
+    status: Arc<Mutex<BotStatus>>,
+    emitter: Arc<dyn EventEmitter>,
+}
+
+impl StatusReporter {
+    pub fn new(
+        session: SessionId,
+        role: BotRole,
+        name: String,
+        emitter: Arc<dyn EventEmitter>,
+    ) -> Self {
+        let status = BotStatus {
+            session,
+            role,
+            name,
+            state: BotState::Connecting,
+        };
+        let reporter = StatusReporter {
+            status: Arc::new(Mutex::new(status)),
+            emitter,
+        };
+        reporter.emit(reporter.get());
+        reporter
+    }
+    pub fn get(&self) -> BotStatus {
+        self.status.lock().unwrap().clone()
+    }
+    pub fn set(&self, state: BotState) {
+        let status = {
+            let mut status = self.status.lock().unwrap();
+            if status.state == state {
+                return;
+            }
+            status.state = state;
+            status.clone()
+        };
+        self.emit(status);
+    }
+    // readyはgatewayの再接続でも来るので，起動時だけReadyにする
+    pub fn set_ready(&self) {
+        if self.get().state == BotState::Connecting {
+            self.set(BotState::Ready);
+        }
+    }
+    fn emit(&self, status: BotStatus) {
+        match serde_json::to_value(status) {
+            Ok(payload) => self.emitter.emit("bot-status-changed", payload),
+            Err(e) => log::error!("failed to serialize bot status: {:?}", e),
+        }
+    }
+}
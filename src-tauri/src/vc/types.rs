@@ -1,10 +1,13 @@
 use std::{collections::HashMap, sync::Arc};
 
 use serde::{Deserialize, Serialize};
-use serenity::all::{ChannelId, GuildId};
-use serenity::model::id::UserId;
+use serenity::all::{ChannelId, GuildChannel, GuildId};
 use songbird::model::id::UserId as VoiceUserId;
 use tokio::sync::RwLock;
+
+use super::directory::MemberInfo;
+use super::relay::{RelayReceiver, RelaySender};
+use super::volume::Volumes;
 #[derive(Clone, Copy, Debug)]
 pub struct JoinInfo {
     pub guild_id: GuildId,
@@ -20,20 +23,57 @@ impl Default for JoinInfo {
     }
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-pub enum PubIdentify {
-    Track1,
-    Track2,
+// Speaker Botごとのtrack名(configのspeakersで定義)
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PubIdentify(pub String);
+
+impl std::fmt::Display for PubIdentify {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// relay sessionの名前(configのsessionsで定義)
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SessionId(pub String);
+
+impl std::fmt::Display for SessionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Listener Botごとの名前(configのlistenersで定義)
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SubIdentify(pub String);
+
+impl std::fmt::Display for SubIdentify {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// trackの音声をどのlistenerにどのgainで流すか
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Route {
+    pub track: PubIdentify,
+    pub listener: SubIdentify,
+    pub gain: f32,
 }
 
 pub struct VoiceType {
     pub user_id: VoiceUserId,
+    pub identify: PubIdentify,
     pub voice_data: Vec<i16>,
 }
 impl VoiceType {
-    pub fn new(user_id: VoiceUserId, voice_data: Vec<i16>) -> Self {
+    pub fn new(user_id: VoiceUserId, identify: PubIdentify, voice_data: Vec<i16>) -> Self {
         VoiceType {
             user_id,
+            identify,
             voice_data,
         }
     }
@@ -43,6 +83,8 @@ impl VoiceType {
 pub enum VoiceUserEvent {
     Join,
     Leave,
+    // Join済みのユーザーの名前やアイコンが分かった
+    Update,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -50,16 +92,65 @@ pub struct UserInfo {
     pub user_id: VoiceUserId,
     pub event: VoiceUserEvent,
     pub identify: PubIdentify,
+    pub guild_id: GuildId,
+    // gatewayのcacheに入っていればREST APIで取りに行かない
+    pub member: Option<MemberInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpeakingInfo {
+    pub user_id: VoiceUserId,
+    pub speaking: bool,
+}
+
+// gatewayが止まったBot(予備Botに切り替える)
+#[derive(Debug, Clone)]
+pub enum DeadBot {
+    Speaker(PubIdentify),
+    Listener(SubIdentify),
+}
+
+// anchorのユーザーが別のVCに移った(speakerを追従させる)
+#[derive(Debug, Clone)]
+pub struct AnchorMoved {
+    pub track: PubIdentify,
+    pub channel_id: ChannelId,
+}
+
+// anchorを追ってspeakerが移ったことをフロントエンドに知らせる
+#[derive(Serialize, Clone, Debug)]
+pub struct SpeakerFollowed {
+    pub session: SessionId,
+    pub track: PubIdentify,
+    pub channel_id: ChannelId,
 }
 
 pub enum SendEnum {
     UserData(UserInfo),
     VoiceData(VoiceType),
+    SpeakingState(SpeakingInfo),
+}
+
+// trackとlistenerごとに選べるVC一覧(Botごとにguildが違う)
+#[derive(Serialize, Clone, Default)]
+pub struct VoiceChannels {
+    pub tracks: HashMap<PubIdentify, Vec<GuildChannel>>,
+    pub listeners: HashMap<SubIdentify, Vec<GuildChannel>>,
 }
 
 pub type VoiceChannelType = SendEnum;
 pub type VoiceManagerSenderType = tokio::sync::mpsc::Sender<VoiceChannelType>;
 pub type VoiceManagerReceiverType = tokio::sync::mpsc::Receiver<VoiceChannelType>;
-pub type VoiceSenderType = tokio::sync::mpsc::Sender<Vec<u8>>;
-pub type VoiceReceiverType = tokio::sync::mpsc::Receiver<Vec<u8>>;
-pub type UserVolumesType = Arc<RwLock<HashMap<UserId, f32>>>;
+pub type VoiceSenderType = RelaySender;
+pub type VoiceReceiverType = RelayReceiver;
+pub type UserVolumesType = Arc<RwLock<Volumes>>;
+pub type RoutesType = Arc<RwLock<Vec<Route>>>;
+// readyで受け取るBotごとのctx
+pub type CtxType = Arc<RwLock<Option<serenity::prelude::Context>>>;
+pub type OutputsType = Arc<RwLock<HashMap<SubIdentify, VoiceSenderType>>>;
+// guildのVCとカテゴリのidと名前
+pub type GuildChannelsType = (Vec<GuildChannel>, HashMap<ChannelId, String>);
+pub type DeadBotSenderType = tokio::sync::mpsc::UnboundedSender<DeadBot>;
+pub type DeadBotReceiverType = tokio::sync::mpsc::UnboundedReceiver<DeadBot>;
+pub type AnchorMovedSenderType = tokio::sync::mpsc::UnboundedSender<AnchorMoved>;
+pub type AnchorMovedReceiverType = tokio::sync::mpsc::UnboundedReceiver<AnchorMoved>;
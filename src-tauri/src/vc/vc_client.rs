@@ -1,151 +1,950 @@
-use std::time::Duration;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
 
-use crate::vc::dis_pub::Pub;
-use crate::vc::dis_sub::Sub;
 use crate::vc::types::JoinInfo;
-use log::error;
+use log::{error, info, warn};
 use serenity::{
-    all::{ChannelId, GuildId, UserId},
+    all::{ChannelId, GuildChannel, GuildId, UserId},
     futures::future::join_all,
 };
-use tauri::AppHandle;
+use tokio::sync::{Mutex, RwLock};
 
 use super::{
-    types::{PubIdentify, UserVolumesType, VoiceChannelType},
+    backend::{VoiceBackend, VoiceSink, VoiceSource},
+    bot_pool::BotPool,
+    config::{ListenerConfig, MatchPreset, SessionConfig, SpeakerConfig},
+    discovery::{self, DiscoveryRule},
+    error::VcError,
+    events::EventEmitter,
+    join_session::{JoinSession, TaskScope},
+    overlay::Overlay,
+    relay::RelayReport,
+    schedule::{Schedule, ScheduledMatch},
+    status::{BotRole, BotStatus, StatusReporter},
+    types::{
+        AnchorMoved, AnchorMovedReceiverType, AnchorMovedSenderType, DeadBot, DeadBotReceiverType,
+        DeadBotSenderType, GuildChannelsType, PubIdentify, Route, SessionId, SpeakerFollowed,
+        SubIdentify, VoiceChannelType, VoiceChannels,
+    },
     voice_manager::VoiceManager,
+    volume::Volumes,
 };
+// Botのready待ちの上限
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Botの起動直後はctxが無いのでreadyになるまで待つ
+// 権限が無いなど，それ以外のエラーはすぐに返す
+async fn wait_voice_channels<Fut>(
+    name: &str,
+    mut fetch: impl FnMut() -> Fut,
+) -> Result<Vec<GuildChannel>, VcError>
+where
+    Fut: Future<Output = Result<Vec<GuildChannel>, VcError>>,
+{
+    let wait = async {
+        loop {
+            match fetch().await {
+                Err(VcError::BotNotReady(_)) => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                result => return result,
+            }
+        }
+    };
+    tokio::time::timeout(READY_TIMEOUT, wait)
+        .await
+        .map_err(|_| VcError::BotNotReady(name.to_owned()))?
+}
+
+async fn wait_ready<Fut>(name: &str, mut is_ready: impl FnMut() -> Fut) -> Result<(), VcError>
+where
+    Fut: Future<Output = bool>,
+{
+    let wait = async {
+        while !is_ready().await {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    tokio::time::timeout(READY_TIMEOUT, wait)
+        .await
+        .map_err(|_| VcError::BotNotReady(name.to_owned()))
+}
+
+// VCのlockを持たずにBotを起動する(failoverでready待ちの間もlockを放す)
+struct Spawner {
+    session_id: SessionId,
+    guild_id: GuildId,
+    bot_pool: Arc<BotPool>,
+    backend: Arc<dyn VoiceBackend>,
+    emitter: Arc<dyn EventEmitter>,
+    dead_tx: DeadBotSenderType,
+    anchor_tx: AnchorMovedSenderType,
+}
+
+impl Spawner {
+    async fn spawn_speaker(
+        &self,
+        speaker: &SpeakerConfig,
+    ) -> Result<Box<dyn VoiceSource>, VcError> {
+        let guild_id = speaker.guild_id.unwrap_or(self.guild_id);
+        loop {
+            let token = match speaker.api.clone().or_else(|| self.bot_pool.acquire()) {
+                Some(token) => token,
+                None => {
+                    return Err(VcError::NoPooledBot(format!(
+                        "{}/{}",
+                        self.session_id, speaker.track
+                    )))
+                }
+            };
+            let started = self
+                .backend
+                .start_source(
+                    speaker.track.clone(),
+                    guild_id,
+                    &token,
+                    self.dead_tx.clone(),
+                    self.anchor_tx.clone(),
+                    self.status_reporter(BotRole::Speaker, speaker.track.to_string()),
+                )
+                .await;
+            match started {
+                Ok(dis_pub) => {
+                    dis_pub.set_anchor(speaker.anchor);
+                    return Ok(dis_pub);
+                }
+                // poolのTokenが無効な場合は次のTokenを試す
+                Err(why) if speaker.api.is_none() => {
+                    error!("pooled token error for {}: {}", speaker.track, why);
+                }
+                Err(why) => {
+                    return Err(VcError::BotStart {
+                        name: format!("{}/{}", self.session_id, speaker.track),
+                        reason: why.to_string(),
+                    })
+                }
+            }
+        }
+    }
+    async fn spawn_listener(
+        &self,
+        listener: &ListenerConfig,
+    ) -> Result<Box<dyn VoiceSink>, VcError> {
+        let guild_id = listener.guild_id.unwrap_or(self.guild_id);
+        loop {
+            let token = match listener.api.clone().or_else(|| self.bot_pool.acquire()) {
+                Some(token) => token,
+                None => {
+                    return Err(VcError::NoPooledBot(format!(
+                        "{}/{}",
+                        self.session_id, listener.name
+                    )))
+                }
+            };
+            let started = self
+                .backend
+                .start_sink(
+                    listener.name.clone(),
+                    guild_id,
+                    &token,
+                    self.dead_tx.clone(),
+                    self.status_reporter(BotRole::Listener, listener.name.to_string()),
+                )
+                .await;
+            match started {
+                Ok(dis_sub) => return Ok(dis_sub),
+                Err(why) if listener.api.is_none() => {
+                    error!("pooled token error for {}: {}", listener.name, why);
+                }
+                Err(why) => {
+                    return Err(VcError::BotStart {
+                        name: format!("{}/{}", self.session_id, listener.name),
+                        reason: why.to_string(),
+                    })
+                }
+            }
+        }
+    }
+    fn status_reporter(&self, role: BotRole, name: String) -> StatusReporter {
+        StatusReporter::new(self.session_id.clone(), role, name, self.emitter.clone())
+    }
+}
+
 pub struct VC {
+    session_id: SessionId,
+    // speaker/listenerごとにguild_idが無い場合の既定値
     guild_id: GuildId,
-    dis_pub: Pub,
-    dis_pub2: Pub,
-    dis_sub: Sub,
+    dis_pubs: Vec<Box<dyn VoiceSource>>,
+    dis_subs: Vec<Box<dyn VoiceSink>>,
     voice_manager: VoiceManager,
-    token: Option<String>,
+    bot_pool: Arc<BotPool>,
+    backend: Arc<dyn VoiceBackend>,
+    emitter: Arc<dyn EventEmitter>,
+    joined: Option<JoinSession>,
+    dead_tx: DeadBotSenderType,
+    dead_rx: Option<DeadBotReceiverType>,
+    anchor_tx: AnchorMovedSenderType,
+    anchor_rx: Option<AnchorMovedReceiverType>,
+    // shutdown後は予備Botに切り替えない
+    stopped: bool,
+    schedule: Schedule,
+    discovery: Option<DiscoveryRule>,
 }
 
 impl VC {
-    pub fn new(guild_id: GuildId, user_volumes: UserVolumesType) -> Self {
+    pub fn new(
+        session_id: SessionId,
+        guild_id: GuildId,
+        voice_manager: VoiceManager,
+        bot_pool: Arc<BotPool>,
+        backend: Arc<dyn VoiceBackend>,
+        emitter: Arc<dyn EventEmitter>,
+    ) -> Self {
+        let (dead_tx, dead_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (anchor_tx, anchor_rx) = tokio::sync::mpsc::unbounded_channel();
         VC {
+            session_id,
             guild_id,
-            dis_pub: Pub::new(PubIdentify::Track1),
-            dis_pub2: Pub::new(PubIdentify::Track2),
-            dis_sub: Sub::new(),
-            voice_manager: VoiceManager::new(user_volumes),
-            token: None,
+            dis_pubs: Vec::new(),
+            dis_subs: Vec::new(),
+            voice_manager,
+            bot_pool,
+            backend,
+            emitter,
+            joined: None,
+            dead_tx,
+            dead_rx: Some(dead_rx),
+            anchor_tx,
+            anchor_rx: Some(anchor_rx),
+            stopped: false,
+            schedule: Schedule::default(),
+            discovery: None,
         }
     }
+    // configのsessionからVCを作る(Botの起動はstart_bot)
+    pub fn from_session(
+        session: &SessionConfig,
+        guild_id: GuildId,
+        overlay: Arc<Overlay>,
+        bot_pool: Arc<BotPool>,
+        backend: Arc<dyn VoiceBackend>,
+        emitter: Arc<dyn EventEmitter>,
+    ) -> Self {
+        let routes = Arc::new(RwLock::new(session.routes()));
+        let user_volumes = Arc::new(RwLock::new(Volumes::from_session(session)));
+        let voice_manager = VoiceManager::new(
+            session.id.clone(),
+            user_volumes,
+            overlay,
+            routes,
+            session.backpressure,
+            backend.clone(),
+        );
+        let mut vc = VC::new(
+            session.id.clone(),
+            guild_id,
+            voice_manager,
+            bot_pool,
+            backend,
+            emitter,
+        );
+        vc.set_schedule(session.schedule.clone());
+        vc.discovery = session.discovery.clone();
+        vc
+    }
     pub async fn start_bot(
         &mut self,
-        pub_token: &str,
-        pub_token2: &str,
-        sub_token: &str,
-    ) -> Result<(), String> {
+        speakers: &[SpeakerConfig],
+        listeners: &[ListenerConfig],
+    ) -> Result<(), VcError> {
         // spawn clients
         // APIで落ちる場合はここでエラーになる
-
-        let mut client_sub = match self.dis_sub.create_client(sub_token).await {
-            Ok(client) => client,
-            Err(why) => return Err(format!("sub token error: {:?}", why)),
+        for listener in listeners {
+            self.start_listener(listener).await?;
+        }
+        for speaker in speakers {
+            self.start_speaker(speaker).await?;
+        }
+        Ok(())
+    }
+    pub async fn start_speaker(&mut self, speaker: &SpeakerConfig) -> Result<(), VcError> {
+        let dis_pub = self.spawner().spawn_speaker(speaker).await?;
+        self.dis_pubs.push(dis_pub);
+        Ok(())
+    }
+    pub async fn start_listener(&mut self, listener: &ListenerConfig) -> Result<(), VcError> {
+        let dis_sub = self.spawner().spawn_listener(listener).await?;
+        self.dis_subs.push(dis_sub);
+        Ok(())
+    }
+    fn spawner(&self) -> Spawner {
+        Spawner {
+            session_id: self.session_id.clone(),
+            guild_id: self.guild_id,
+            bot_pool: self.bot_pool.clone(),
+            backend: self.backend.clone(),
+            emitter: self.emitter.clone(),
+            dead_tx: self.dead_tx.clone(),
+            anchor_tx: self.anchor_tx.clone(),
+        }
+    }
+    pub fn get_status(&self) -> Vec<BotStatus> {
+        let speakers = self.dis_pubs.iter().map(|dis_pub| dis_pub.status());
+        let listeners = self.dis_subs.iter().map(|dis_sub| dis_sub.status());
+        speakers.chain(listeners).collect()
+    }
+    // 全Botがreadyになるまで待つ(起動直後にJoinする場合)
+    pub async fn wait_bots_ready(&self) -> Result<(), VcError> {
+        for dis_pub in &self.dis_pubs {
+            wait_ready(&dis_pub.identify().0, || dis_pub.is_ready()).await?;
+        }
+        for dis_sub in &self.dis_subs {
+            wait_ready(&dis_sub.identify().0, || dis_sub.is_ready()).await?;
+        }
+        Ok(())
+    }
+    // gatewayが止まったBotを監視して予備Botに切り替える
+    // anchorのユーザーが移動したらspeakerを追従させる
+    pub async fn supervise(vc: Arc<Mutex<VC>>) {
+        let (session_id, mut dead_rx, mut anchor_rx) = {
+            let mut vc = vc.lock().await;
+            match (vc.dead_rx.take(), vc.anchor_rx.take()) {
+                (Some(dead_rx), Some(anchor_rx)) => (vc.session_id.clone(), dead_rx, anchor_rx),
+                _ => return,
+            }
         };
-        let mut client_pub = match self.dis_pub.create_client(pub_token).await {
-            Ok(client) => client,
-            Err(why) => return Err(format!("pub token error: {:?}", why)),
+        loop {
+            tokio::select! {
+                Some(dead) = dead_rx.recv() => {
+                    if vc.lock().await.stopped {
+                        break;
+                    }
+                    warn!("{}: {:?} gateway stopped", session_id, dead);
+                    if let Err(e) = VC::failover(&vc, dead).await {
+                        error!("{}: failover error: {}", session_id, e);
+                    }
+                }
+                Some(moved) = anchor_rx.recv() => {
+                    let mut vc = vc.lock().await;
+                    if vc.stopped {
+                        break;
+                    }
+                    if let Err(e) = vc.follow_anchor(moved).await {
+                        error!("{}: follow error: {}", vc.session_id, e);
+                    }
+                }
+                else => break,
+            }
+        }
+    }
+    // Join中のspeakerをanchorのユーザーがいるVCに移す
+    async fn follow_anchor(&mut self, moved: AnchorMoved) -> Result<(), VcError> {
+        let joined = match &self.joined {
+            Some(joined) => joined,
+            None => return Ok(()),
         };
-        let mut client_pub2 = match self.dis_pub2.create_client(pub_token2).await {
-            Ok(client) => client,
-            Err(why) => return Err(format!("pub2 token error: {:?}", why)),
+        match joined.pub_infos.get(&moved.track) {
+            Some(channel_id) if *channel_id != moved.channel_id => {}
+            _ => return Ok(()),
+        }
+        self.switch_channel(BotRole::Speaker, &moved.track.0, moved.channel_id)
+            .await?;
+        let followed = SpeakerFollowed {
+            session: self.session_id.clone(),
+            track: moved.track,
+            channel_id: moved.channel_id,
         };
-        self.token = Some(pub_token.to_owned());
-
-        tokio::spawn(async move {
-            if let Err(why) = client_pub.start().await {
-                error!("Err with pub client channle: {:?}", why);
-            }
-        });
-        tokio::spawn(async move {
-            if let Err(why) = client_pub2.start().await {
-                error!("Err with pub2 client channel: {:?}", why);
+        match serde_json::to_value(followed) {
+            Ok(payload) => self.emitter.emit("speaker-followed", payload),
+            Err(e) => error!("failed to serialize followed speaker: {:?}", e),
+        }
+        Ok(())
+    }
+    // bot_poolの予備Botを起動して同じチャンネルに入り直す
+    // 止まったBotは先に退出させて閉じる(再接続して音声が二重に流れないように)
+    // 予備Botの起動とready待ちの間はVCのlockを放す
+    async fn failover(vc: &Mutex<VC>, dead: DeadBot) -> Result<(), VcError> {
+        match dead {
+            DeadBot::Speaker(track) => {
+                let (index, old, spawner) = {
+                    let mut vc = vc.lock().await;
+                    let index = vc
+                        .dis_pubs
+                        .iter()
+                        .position(|dis_pub| *dis_pub.identify() == track)
+                        .ok_or_else(|| VcError::UnknownTrack(track.clone()))?;
+                    (index, vc.dis_pubs.remove(index), vc.spawner())
+                };
+                let is_listening = old.is_listening();
+                let speaker = SpeakerConfig {
+                    track: track.clone(),
+                    api: None,
+                    guild_id: Some(old.guild_id()),
+                    anchor: old.anchor(),
+                };
+                if let Err(e) = old.leave(old.guild_id()).await {
+                    warn!("{} leave error: {}", track, e);
+                }
+                old.shutdown().await;
+                let started = match spawner.spawn_speaker(&speaker).await {
+                    Ok(dis_pub) => wait_ready(&track.0, || dis_pub.is_ready())
+                        .await
+                        .map(|_| dis_pub),
+                    Err(e) => Err(e),
+                };
+                let mut vc = vc.lock().await;
+                let dis_pub = match started {
+                    Ok(dis_pub) => dis_pub,
+                    Err(e) => {
+                        // 切り替えられなかったtrackも一覧には残す
+                        let index = index.min(vc.dis_pubs.len());
+                        vc.dis_pubs.insert(index, old);
+                        return Err(e);
+                    }
+                };
+                if vc.stopped {
+                    dis_pub.shutdown().await;
+                    return Ok(());
+                }
+                dis_pub.set_is_listening(is_listening);
+                if let Some(joined) = &vc.joined {
+                    if let Some(channel_id) = joined.pub_infos.get(&track) {
+                        let join_info = JoinInfo {
+                            guild_id: dis_pub.guild_id(),
+                            channel_id: *channel_id,
+                        };
+                        let tx = joined.manager_tx.clone();
+                        let metrics = vc.voice_manager.input_metrics(&track);
+                        if let Err(e) = dis_pub.join(join_info, tx, metrics, &joined.tasks).await {
+                            error!("{}: rejoin error: {}", vc.session_id, e);
+                        }
+                    }
+                }
+                info!(
+                    "{}/{} switched to standby bot {:?}",
+                    vc.session_id,
+                    track,
+                    dis_pub.user_id()
+                );
+                let index = index.min(vc.dis_pubs.len());
+                vc.dis_pubs.insert(index, dis_pub);
             }
-        });
-        tokio::spawn(async move {
-            if let Err(why) = client_sub.start().await {
-                error!("Err with sub client channel: {:?}", why);
+            DeadBot::Listener(name) => {
+                let (index, old, spawner) = {
+                    let mut vc = vc.lock().await;
+                    let index = vc
+                        .dis_subs
+                        .iter()
+                        .position(|dis_sub| *dis_sub.identify() == name)
+                        .ok_or_else(|| VcError::UnknownListener(name.clone()))?;
+                    (index, vc.dis_subs.remove(index), vc.spawner())
+                };
+                let listener = ListenerConfig {
+                    name: name.clone(),
+                    api: None,
+                    guild_id: Some(old.guild_id()),
+                };
+                if let Err(e) = old.leave(old.guild_id()).await {
+                    warn!("{} leave error: {}", name, e);
+                }
+                old.shutdown().await;
+                let started = match spawner.spawn_listener(&listener).await {
+                    Ok(dis_sub) => wait_ready(&name.0, || dis_sub.is_ready())
+                        .await
+                        .map(|_| dis_sub),
+                    Err(e) => Err(e),
+                };
+                let mut vc = vc.lock().await;
+                let dis_sub = match started {
+                    Ok(dis_sub) => dis_sub,
+                    Err(e) => {
+                        let index = index.min(vc.dis_subs.len());
+                        vc.dis_subs.insert(index, old);
+                        return Err(e);
+                    }
+                };
+                if vc.stopped {
+                    dis_sub.shutdown().await;
+                    return Ok(());
+                }
+                if let Some(joined) = &vc.joined {
+                    if let Some(channel_id) = joined.sub_infos.get(&name) {
+                        let join_info = JoinInfo {
+                            guild_id: dis_sub.guild_id(),
+                            channel_id: *channel_id,
+                        };
+                        // 古い送信先はdropされて再生taskが終わる
+                        let vc_rx = vc.voice_manager.open_output(name.clone()).await;
+                        if let Err(e) = dis_sub.join(join_info, vc_rx, &joined.tasks).await {
+                            error!("{}: rejoin error: {}", vc.session_id, e);
+                        }
+                    }
+                }
+                info!(
+                    "{}/{} switched to standby bot {:?}",
+                    vc.session_id,
+                    name,
+                    dis_sub.user_id()
+                );
+                let index = index.min(vc.dis_subs.len());
+                vc.dis_subs.insert(index, dis_sub);
             }
-        });
+        }
+        Ok(())
+    }
+    // 起動中にtrackを増やす(新しいtrackは全listenerに流す)
+    pub async fn add_speaker(&mut self, speaker: &SpeakerConfig) -> Result<(), VcError> {
+        if self.tracks().contains(&speaker.track) {
+            return Err(VcError::AlreadyExists(speaker.track.to_string()));
+        }
+        self.start_speaker(speaker).await?;
+        for listener in self.listeners() {
+            self.voice_manager
+                .update_route(speaker.track.clone(), listener, 1.)
+                .await;
+        }
         Ok(())
     }
+    pub async fn add_listener(&mut self, listener: &ListenerConfig) -> Result<(), VcError> {
+        if self.listeners().contains(&listener.name) {
+            return Err(VcError::AlreadyExists(listener.name.to_string()));
+        }
+        self.start_listener(listener).await?;
+        for track in self.tracks() {
+            self.voice_manager
+                .update_route(track, listener.name.clone(), 1.)
+                .await;
+        }
+        Ok(())
+    }
+    pub fn session_id(&self) -> &SessionId {
+        &self.session_id
+    }
+    pub fn tracks(&self) -> Vec<PubIdentify> {
+        self.dis_pubs
+            .iter()
+            .map(|dis_pub| dis_pub.identify().clone())
+            .collect()
+    }
+    pub fn listeners(&self) -> Vec<SubIdentify> {
+        self.dis_subs
+            .iter()
+            .map(|dis_sub| dis_sub.identify().clone())
+            .collect()
+    }
     pub async fn join(
-        &self,
-        app: AppHandle,
-        pub_info: ChannelId,
-        pub_info2: ChannelId,
-        sub_info: ChannelId,
-    ) {
-        let (manager_tx, manager_rx) = tokio::sync::mpsc::channel::<VoiceChannelType>(16);
-        let (vc_tx, vc_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(256);
-        if self.token.is_none() {
-            return;
+        &mut self,
+        pub_infos: HashMap<PubIdentify, ChannelId>,
+        sub_infos: HashMap<SubIdentify, ChannelId>,
+    ) -> Result<(), VcError> {
+        // 前のJoinのtaskを残さない
+        if self.joined.is_some() {
+            self.leave().await;
         }
-        let token = self.token.clone().unwrap();
-        // Noneの時は上ではじいてるので，
-        let futures = vec![
-            self.dis_pub.join(
+        let tasks = TaskScope::new();
+        let (manager_tx, manager_rx) = tokio::sync::mpsc::channel::<VoiceChannelType>(16);
+        let mut futures = Vec::new();
+        for dis_pub in &self.dis_pubs {
+            let channel_id = match pub_infos.get(dis_pub.identify()) {
+                Some(channel_id) => *channel_id,
+                None => {
+                    warn!("no channel selected for {}", dis_pub.identify());
+                    continue;
+                }
+            };
+            futures.push(dis_pub.join(
                 JoinInfo {
-                    guild_id: self.guild_id,
-                    channel_id: pub_info,
+                    guild_id: dis_pub.guild_id(),
+                    channel_id,
                 },
                 manager_tx.clone(),
-            ),
-            self.dis_pub2.join(
-                JoinInfo {
-                    guild_id: self.guild_id,
-                    channel_id: pub_info2,
-                },
-                manager_tx,
-            ),
-        ];
-        join_all(futures).await;
-        self.voice_manager.start(app, token, manager_rx, vc_tx);
-        self.dis_sub
-            .join(
+                self.voice_manager.input_metrics(dis_pub.identify()),
+                &tasks,
+            ));
+        }
+        let mut results = join_all(futures).await;
+
+        let mut futures = Vec::new();
+        for dis_sub in &self.dis_subs {
+            let channel_id = match sub_infos.get(dis_sub.identify()) {
+                Some(channel_id) => *channel_id,
+                None => {
+                    warn!("no channel selected for {}", dis_sub.identify());
+                    continue;
+                }
+            };
+            let vc_rx = self
+                .voice_manager
+                .open_output(dis_sub.identify().clone())
+                .await;
+            futures.push(dis_sub.join(
                 JoinInfo {
-                    guild_id: self.guild_id,
-                    channel_id: sub_info,
+                    guild_id: dis_sub.guild_id(),
+                    channel_id,
                 },
                 vc_rx,
-            )
-            .await;
+                &tasks,
+            ));
+        }
+        self.voice_manager
+            .start(self.emitter.clone(), manager_rx, &tasks);
+        results.extend(join_all(futures).await);
+        self.joined = Some(JoinSession {
+            manager_tx,
+            pub_infos,
+            sub_infos,
+            tasks,
+        });
+        // 入れなかったBotがあれば最初のエラーを返す(入れたBotはそのまま)
+        results.into_iter().collect()
     }
 
-    pub async fn leave(&self) {
-        let guild_id = self.guild_id;
-        self.dis_pub.leave(guild_id).await.unwrap();
-        self.dis_pub2.leave(guild_id).await.unwrap();
-        self.dis_sub.leave(guild_id).await.unwrap();
+    pub async fn leave(&mut self) {
+        let joined = self.joined.take();
+        for dis_pub in &self.dis_pubs {
+            // channel未選択のtrackはVCにいない
+            if let Err(e) = dis_pub.leave(dis_pub.guild_id()).await {
+                warn!("{} leave error: {}", dis_pub.identify(), e);
+            }
+        }
+        for dis_sub in &self.dis_subs {
+            if let Err(e) = dis_sub.leave(dis_sub.guild_id()).await {
+                warn!("{} leave error: {}", dis_sub.identify(), e);
+            }
+        }
+        // 再接続やVoiceManager，再生のtaskが終わるまで待つ
+        if let Some(joined) = joined {
+            joined.tasks.shutdown().await;
+        }
+        self.voice_manager.clear_outputs().await;
+        self.voice_manager.clear_overlay().await;
     }
 
-    pub async fn get_voice_channels(&self) -> Vec<serenity::all::GuildChannel> {
-        loop {
-            let res = self.dis_sub.get_voice_channels(self.guild_id).await;
-            if let Ok(voice_channels) = res {
-                return voice_channels;
+    // Join中のBotを1体だけ別のチャンネルに移す(VoiceManager，音量，聞き取り状態はそのまま)
+    pub async fn switch_channel(
+        &mut self,
+        role: BotRole,
+        name: &str,
+        channel_id: ChannelId,
+    ) -> Result<(), VcError> {
+        let joined = self
+            .joined
+            .as_mut()
+            .ok_or_else(|| VcError::NotInVc(name.to_owned()))?;
+        match role {
+            BotRole::Speaker => {
+                let track = PubIdentify(name.to_owned());
+                let dis_pub = self
+                    .dis_pubs
+                    .iter()
+                    .find(|dis_pub| *dis_pub.identify() == track)
+                    .ok_or_else(|| VcError::UnknownTrack(track.clone()))?;
+                if joined.pub_infos.contains_key(&track) {
+                    dis_pub.switch_channel(channel_id).await?;
+                } else {
+                    // Join時にchannel未選択だったtrack
+                    let join_info = JoinInfo {
+                        guild_id: dis_pub.guild_id(),
+                        channel_id,
+                    };
+                    let tx = joined.manager_tx.clone();
+                    let metrics = self.voice_manager.input_metrics(&track);
+                    dis_pub.join(join_info, tx, metrics, &joined.tasks).await?;
+                }
+                info!("{}/{} switched to {}", self.session_id, track, channel_id);
+                // failover時もこのチャンネルに入る
+                joined.pub_infos.insert(track, channel_id);
+            }
+            BotRole::Listener => {
+                let listener = SubIdentify(name.to_owned());
+                let dis_sub = self
+                    .dis_subs
+                    .iter()
+                    .find(|dis_sub| *dis_sub.identify() == listener)
+                    .ok_or_else(|| VcError::UnknownListener(listener.clone()))?;
+                if joined.sub_infos.contains_key(&listener) {
+                    dis_sub.switch_channel(channel_id).await?;
+                } else {
+                    let join_info = JoinInfo {
+                        guild_id: dis_sub.guild_id(),
+                        channel_id,
+                    };
+                    let vc_rx = self.voice_manager.open_output(listener.clone()).await;
+                    dis_sub.join(join_info, vc_rx, &joined.tasks).await?;
+                }
+                info!(
+                    "{}/{} switched to {}",
+                    self.session_id, listener, channel_id
+                );
+                joined.sub_infos.insert(listener, channel_id);
             }
-            tokio::time::sleep(Duration::from_millis(100)).await;
         }
+        Ok(())
+    }
+
+    // presetのチャンネルに入り，聞き取り状態を合わせる
+    // Join中ならチャンネルが変わるBotだけ移す(入れなかったBotがあれば最初のエラーを返す)
+    pub async fn apply_preset(&mut self, preset: &MatchPreset) -> Result<(), VcError> {
+        let tracks = self.tracks();
+        let listeners = self.listeners();
+        let mut results = Vec::new();
+        match &self.joined {
+            None => {
+                results.push(
+                    self.join(preset.speakers.clone(), preset.listeners.clone())
+                        .await,
+                );
+            }
+            Some(joined) => {
+                let mut moves = Vec::new();
+                for (track, channel_id) in &preset.speakers {
+                    if tracks.contains(track) && joined.pub_infos.get(track) != Some(channel_id) {
+                        moves.push((BotRole::Speaker, track.0.clone(), *channel_id));
+                    }
+                }
+                for (listener, channel_id) in &preset.listeners {
+                    if listeners.contains(listener)
+                        && joined.sub_infos.get(listener) != Some(channel_id)
+                    {
+                        moves.push((BotRole::Listener, listener.0.clone(), *channel_id));
+                    }
+                }
+                for (role, name, channel_id) in moves {
+                    results.push(self.switch_channel(role, &name, channel_id).await);
+                }
+            }
+        }
+        for track in tracks {
+            let is_listening = preset.listening.contains(&track);
+            results.push(self.update_is_listening(track, is_listening).await);
+        }
+        info!("{}: applied preset {}", self.session_id, preset.name);
+        results.into_iter().collect()
+    }
+
+    pub fn set_schedule(&mut self, matches: Vec<ScheduledMatch>) {
+        self.schedule = Schedule::new(matches);
+    }
+    pub fn get_schedule(&self) -> Schedule {
+        self.schedule.clone()
+    }
+    // スケジュールの試合のチャンネルにBotを移す(入れなかったBotがあっても今の試合は進める)
+    // チャンネルの無い試合(start.gg/Challongeから読み込んだ試合)はdiscoveryのルールで探す
+    pub async fn go_to_match(&mut self, match_id: &str) -> Result<(), VcError> {
+        let mut preset = self
+            .schedule
+            .find(match_id)
+            .ok_or_else(|| VcError::UnknownMatch(match_id.to_owned()))?
+            .to_preset();
+        if preset.speakers.is_empty() && preset.listeners.is_empty() {
+            let discovered = self.discover_match(match_id).await?;
+            preset.speakers = discovered.speakers;
+            preset.listeners = discovered.listeners;
+            preset.listening = discovered.listening;
+        }
+        self.schedule.current = Some(preset.name.clone());
+        self.apply_preset(&preset).await
+    }
+    pub async fn next_match(&mut self) -> Result<(), VcError> {
+        let match_id = self
+            .schedule
+            .next()
+            .ok_or_else(|| VcError::Schedule("no next match".to_owned()))?
+            .id
+            .clone();
+        self.go_to_match(&match_id).await
     }
 
-    pub async fn update_is_listening(&self, identify: PubIdentify, is_listening: bool) {
-        match identify {
-            PubIdentify::Track1 => {
-                self.dis_pub.set_is_listening(is_listening).await;
+    // discoveryのルールで試合番号のチャンネルを探す(見つからないBotはそのまま)
+    pub async fn discover_match(&self, match_no: &str) -> Result<MatchPreset, VcError> {
+        let rule = self
+            .discovery
+            .as_ref()
+            .ok_or_else(|| VcError::Schedule("no discovery rule in config".to_owned()))?;
+        // 同じguildは1回だけ取得する
+        let mut guild_channels: HashMap<GuildId, GuildChannelsType> = HashMap::new();
+        for dis_pub in &self.dis_pubs {
+            let guild_id = dis_pub.guild_id();
+            if let Entry::Vacant(entry) = guild_channels.entry(guild_id) {
+                entry.insert(dis_pub.get_channels(guild_id).await?);
             }
-            PubIdentify::Track2 => {
-                self.dis_pub2.set_is_listening(is_listening).await;
+        }
+        if rule.listeners.is_some() {
+            for dis_sub in &self.dis_subs {
+                let guild_id = dis_sub.guild_id();
+                if let Entry::Vacant(entry) = guild_channels.entry(guild_id) {
+                    entry.insert(dis_sub.get_channels(guild_id).await?);
+                }
             }
         }
+        let category = rule
+            .category
+            .as_deref()
+            .map(|category| discovery::fill(category, match_no));
+        let find = |guild_id: GuildId, name: &str| {
+            let (channels, categories) = guild_channels.get(&guild_id)?;
+            let channel_id =
+                discovery::find_channel(channels, categories, category.as_deref(), name);
+            if channel_id.is_none() {
+                warn!("{}: no channel named {}", self.session_id, name);
+            }
+            channel_id
+        };
+
+        let mut preset = MatchPreset {
+            name: format!("Match {}", match_no),
+            ..Default::default()
+        };
+        let names = discovery::expand(&rule.speakers);
+        for (i, dis_pub) in self.dis_pubs.iter().enumerate() {
+            let name = match discovery::name_for(&names, i) {
+                Some(name) => name,
+                None => continue,
+            };
+            let name = discovery::fill(name, match_no);
+            if let Some(channel_id) = find(dis_pub.guild_id(), &name) {
+                let track = dis_pub.identify().clone();
+                preset.listening.push(track.clone());
+                preset.speakers.insert(track, channel_id);
+            }
+        }
+        if let Some(listeners) = &rule.listeners {
+            let names = discovery::expand(listeners);
+            for (i, dis_sub) in self.dis_subs.iter().enumerate() {
+                let name = match discovery::name_for(&names, i) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let name = discovery::fill(name, match_no);
+                if let Some(channel_id) = find(dis_sub.guild_id(), &name) {
+                    preset
+                        .listeners
+                        .insert(dis_sub.identify().clone(), channel_id);
+                }
+            }
+        }
+        if preset.speakers.is_empty() && preset.listeners.is_empty() {
+            return Err(VcError::UnknownMatch(match_no.to_owned()));
+        }
+        Ok(preset)
+    }
+
+    // 終了時: 聞き取りを止めて全VCから退出し，gatewayを閉じる
+    pub async fn shutdown(&mut self) {
+        if self.stopped {
+            return;
+        }
+        self.stopped = true;
+        for dis_pub in &self.dis_pubs {
+            dis_pub.set_is_listening(false);
+        }
+        if self.joined.is_some() {
+            self.leave().await;
+        }
+        let pubs = self.dis_pubs.iter().map(|dis_pub| dis_pub.shutdown());
+        let subs = self.dis_subs.iter().map(|dis_sub| dis_sub.shutdown());
+        join_all(pubs).await;
+        join_all(subs).await;
+        info!("{}: shutdown", self.session_id);
+    }
+
+    pub async fn get_voice_channels(&self) -> Result<VoiceChannels, VcError> {
+        // 同じguildは1回だけ取得する
+        let mut guild_channels: HashMap<GuildId, Vec<GuildChannel>> = HashMap::new();
+        let mut voice_channels = VoiceChannels::default();
+        for dis_pub in &self.dis_pubs {
+            let guild_id = dis_pub.guild_id();
+            if let Entry::Vacant(entry) = guild_channels.entry(guild_id) {
+                let channels = wait_voice_channels(&dis_pub.identify().0, || {
+                    dis_pub.get_voice_channels(guild_id)
+                })
+                .await?;
+                entry.insert(channels);
+            }
+            voice_channels.tracks.insert(
+                dis_pub.identify().clone(),
+                guild_channels[&guild_id].clone(),
+            );
+        }
+        for dis_sub in &self.dis_subs {
+            let guild_id = dis_sub.guild_id();
+            if let Entry::Vacant(entry) = guild_channels.entry(guild_id) {
+                let channels = wait_voice_channels(&dis_sub.identify().0, || {
+                    dis_sub.get_voice_channels(guild_id)
+                })
+                .await?;
+                entry.insert(channels);
+            }
+            voice_channels.listeners.insert(
+                dis_sub.identify().clone(),
+                guild_channels[&guild_id].clone(),
+            );
+        }
+        Ok(voice_channels)
+    }
+
+    pub async fn update_is_listening(
+        &self,
+        identify: PubIdentify,
+        is_listening: bool,
+    ) -> Result<(), VcError> {
+        let dis_pub = self
+            .dis_pubs
+            .iter()
+            .find(|dis_pub| *dis_pub.identify() == identify)
+            .ok_or(VcError::UnknownTrack(identify))?;
+        dis_pub.set_is_listening(is_listening);
+        Ok(())
+    }
+
+    // 追従するユーザーを変える(Noneで追従しない)
+    pub fn set_anchor(&self, track: PubIdentify, anchor: Option<UserId>) -> Result<(), VcError> {
+        let dis_pub = self
+            .dis_pubs
+            .iter()
+            .find(|dis_pub| *dis_pub.identify() == track)
+            .ok_or(VcError::UnknownTrack(track))?;
+        dis_pub.set_anchor(anchor);
+        Ok(())
+    }
+    pub fn get_anchors(&self) -> HashMap<PubIdentify, UserId> {
+        self.dis_pubs
+            .iter()
+            .filter_map(|dis_pub| Some((dis_pub.identify().clone(), dis_pub.anchor()?)))
+            .collect()
+    }
+
+    pub async fn update_volume(&self, track: Option<&PubIdentify>, user_id: UserId, volume: f32) {
+        self.voice_manager
+            .update_volume(track, user_id, volume)
+            .await;
+    }
+
+    pub async fn update_route(
+        &self,
+        track: PubIdentify,
+        listener: SubIdentify,
+        gain: f32,
+    ) -> Result<(), VcError> {
+        if !self.tracks().contains(&track) {
+            return Err(VcError::UnknownTrack(track));
+        }
+        if !self.listeners().contains(&listener) {
+            return Err(VcError::UnknownListener(listener));
+        }
+        self.voice_manager.update_route(track, listener, gain).await;
+        Ok(())
+    }
+
+    pub async fn get_relay_stats(&self) -> RelayReport {
+        self.voice_manager.relay_stats().await
     }
 
-    pub async fn update_volume(&self, user_id: UserId, volume: f32) {
-        self.voice_manager.update_volume(user_id, volume).await;
+    pub async fn get_routes(&self) -> Vec<Route> {
+        self.voice_manager.get_routes().await
     }
 }
@@ -1,25 +1,35 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
 
-use log::{debug, info};
+use log::{debug, error, info};
 use serde::Serialize;
-use serenity::model::id::UserId;
-use tauri::{AppHandle, Emitter};
+use serenity::model::id::{GuildId, UserId};
+use songbird::model::id::UserId as VoiceUserId;
+use tokio::sync::{mpsc, RwLock};
 
 use crate::vc::types::VoiceUserEvent;
 
+use super::backend::VoiceBackend;
+use super::directory::{MemberDirectory, MemberInfo};
+use super::events::EventEmitter;
+use super::join_session::TaskScope;
+use super::overlay::Overlay;
+use super::relay::{relay_channel, BackpressurePolicy, RelayMetrics, RelayReport, TrackStats};
 use super::types::{
-    PubIdentify, SendEnum, UserInfo, UserVolumesType, VoiceManagerReceiverType, VoiceSenderType,
+    OutputsType, PubIdentify, Route, RoutesType, SendEnum, SessionId, SubIdentify, UserInfo,
+    UserVolumesType, VoiceManagerReceiverType, VoiceReceiverType,
 };
-use songbird::model::id::UserId as VoiceUserId;
 
-fn i16tof32(pcm_data: Vec<i16>) -> Vec<f32> {
+fn i16tof32(pcm_data: &[i16]) -> Vec<f32> {
     pcm_data
         .iter()
         .map(|sample| (*sample as f32) / 32768.0)
         .collect()
 }
 // Vec<i16>のpcmデータからpcm f32用のVec<u8>の音声データを作成
-fn convert_voice_data(data: Vec<i16>, volume: f32) -> Vec<u8> {
+fn convert_voice_data(data: &[i16], volume: f32) -> Vec<u8> {
     let raw = i16tof32(data);
     let bytes: Vec<u8> = raw
         .iter()
@@ -30,79 +40,206 @@ fn convert_voice_data(data: Vec<i16>, volume: f32) -> Vec<u8> {
 
 #[derive(Serialize, Clone)]
 struct EmitData {
-    pub user_id: VoiceUserId,
+    pub session: SessionId,
+    // フロントエンドで桁が落ちないように文字列で送る
+    pub user_id: UserId,
     pub event: VoiceUserEvent,
     pub identify: PubIdentify,
+    // このtrackでの音量
+    pub volume: f32,
+    // 表示名(ニックネーム > 表示名 > ユーザー名)
     pub name: String,
+    pub username: String,
+    pub nick: Option<String>,
+    pub global_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+fn emit_user_data(emitter: &Arc<dyn EventEmitter>, emit_data: EmitData) {
+    match serde_json::to_value(emit_data) {
+        Ok(payload) => emitter.emit("user-data-changed", payload),
+        Err(e) => error!("failed to serialize user data: {:?}", e),
+    }
 }
 
 impl EmitData {
-    pub fn new(user_info: UserInfo, name: String) -> Self {
+    pub fn new(session: SessionId, user_info: UserInfo, member: MemberInfo, volume: f32) -> Self {
         let UserInfo {
             user_id,
             event,
             identify,
+            ..
         } = user_info;
         EmitData {
-            user_id,
+            session,
+            user_id: UserId::new(user_id.0),
             event,
             identify,
-            name,
+            volume,
+            name: member.display_name,
+            username: member.username,
+            nick: member.nick,
+            global_name: member.global_name,
+            avatar_url: member.avatar_url,
         }
     }
 }
 
 pub struct VoiceManager {
+    session_id: SessionId,
     // user_volumes: Arc<Mutex<HashMap<UserId, f32>>>,
     // http: Http,
     user_volumes: UserVolumesType,
     // cache:Arc<Cache>
+    overlay: Arc<Overlay>,
+    routes: RoutesType,
+    // listenerごとの送信先(failoverで差し替える)
+    outputs: OutputsType,
+    backpressure: BackpressurePolicy,
+    // trackごとの捨てた音声の数(failoverで起動し直しても引き継ぐ)
+    inputs: Mutex<HashMap<PubIdentify, Arc<RelayMetrics>>>,
+    directory: Arc<MemberDirectory>,
 }
 
 impl VoiceManager {
-    pub fn new(user_volumes: UserVolumesType) -> Self {
+    pub fn new(
+        session_id: SessionId,
+        user_volumes: UserVolumesType,
+        overlay: Arc<Overlay>,
+        routes: RoutesType,
+        backpressure: BackpressurePolicy,
+        backend: Arc<dyn VoiceBackend>,
+    ) -> Self {
         let user_volumes = user_volumes.clone();
-        VoiceManager { user_volumes }
+        VoiceManager {
+            session_id,
+            user_volumes,
+            overlay,
+            routes,
+            outputs: Arc::new(RwLock::new(HashMap::new())),
+            backpressure,
+            inputs: Mutex::new(HashMap::new()),
+            directory: Arc::new(MemberDirectory::new(backend)),
+        }
     }
-    // Spawn manager task
+    // Spawn manager task(leaveでtasksごと止める)
     pub fn start(
         &self,
-        app: AppHandle,
-        token: String,
+        emitter: Arc<dyn EventEmitter>,
         mut rx: VoiceManagerReceiverType,
-        tx: VoiceSenderType,
+        tasks: &TaskScope,
     ) {
         // let http = self.http
         let user_volumes = self.user_volumes.clone();
-        tokio::spawn(async move {
-            let http = serenity::http::Http::new(&token);
-            let id_name_map: HashMap<UserId, String> = HashMap::new();
-            while let Some(d) = rx.recv().await {
+        let overlay = self.overlay.clone();
+        let session_id = self.session_id.clone();
+        let routes = self.routes.clone();
+        let outputs = self.outputs.clone();
+        let directory = self.directory.clone();
+        let lookups = tasks.clone();
+        // REST APIでの名前の取得は別taskで待ち，終わったらここに戻す(待つ間も音声の中継を止めない)
+        let (resolved_tx, mut resolved_rx) = mpsc::unbounded_channel::<(GuildId, MemberInfo)>();
+        tasks.spawn(async move {
+            // 今VCにいるユーザー(名前が分かったらUpdateを送る)
+            let mut present: HashMap<(PubIdentify, VoiceUserId), GuildId> = HashMap::new();
+            // 取得中のユーザー(同じユーザーを何度も取りに行かない)
+            let mut pending: HashSet<(GuildId, UserId)> = HashSet::new();
+            loop {
+                let d = tokio::select! {
+                    d = rx.recv() => match d {
+                        Some(d) => d,
+                        None => break,
+                    },
+                    Some((guild_id, member)) = resolved_rx.recv() => {
+                        pending.remove(&(guild_id, member.user_id));
+                        let tracks: Vec<PubIdentify> = present
+                            .iter()
+                            .filter(|((_, user_id), g)| {
+                                **g == guild_id && user_id.0 == member.user_id.get()
+                            })
+                            .map(|((track, _), _)| track.clone())
+                            .collect();
+                        for track in tracks {
+                            let user_info = UserInfo {
+                                user_id: VoiceUserId(member.user_id.get()),
+                                event: VoiceUserEvent::Update,
+                                identify: track,
+                                guild_id,
+                                member: None,
+                            };
+                            overlay
+                                .join_user(
+                                    &session_id,
+                                    user_info.user_id,
+                                    member.display_name.clone(),
+                                    member.avatar_url.clone(),
+                                    user_info.identify.clone(),
+                                )
+                                .await;
+                            let volume = user_volumes
+                                .read()
+                                .await
+                                .get(&user_info.identify, member.user_id);
+                            let emit_data =
+                                EmitData::new(session_id.clone(), user_info, member.clone(), volume);
+                            emit_user_data(&emitter, emit_data);
+                        }
+                        continue;
+                    }
+                };
                 match d {
                     SendEnum::UserData(user_info) => {
                         // ~~普通に考えて，VC内で頻繁に出入りしなくない？~~
                         // 一定時間で再Hitする可能性はある
                         debug!("create user_id from {:?}", user_info.user_id);
                         let user_id = UserId::new(user_info.user_id.0);
-                        let user_name = match id_name_map.get(&user_id) {
-                            Some(user_name) => user_name.to_owned(),
+                        let guild_id = user_info.guild_id;
+                        let member = match user_info.member.clone() {
+                            Some(member) => {
+                                directory.insert(guild_id, member.clone()).await;
+                                member
+                            }
                             None => {
-                                let user = http.get_user(user_id).await.unwrap();
-                                user.name
+                                let (member, expired) = directory.lookup(guild_id, user_id).await;
+                                // 分かるまではcacheの情報(無ければid)で表示する
+                                if expired
+                                    && user_info.event != VoiceUserEvent::Leave
+                                    && pending.insert((guild_id, user_id))
+                                {
+                                    let directory = directory.clone();
+                                    let resolved_tx = resolved_tx.clone();
+                                    lookups.spawn(async move {
+                                        let member = directory.fetch(guild_id, user_id).await;
+                                        let _ = resolved_tx.send((guild_id, member));
+                                    });
+                                }
+                                member
                             }
                         };
-                        let emit_data = EmitData::new(user_info, user_name.clone());
-                        app.emit("user-data-changed", emit_data).unwrap();
-                        {
-                            let user_lock = user_volumes.read().await;
-                            let need_insert = user_lock.get(&user_id).is_none();
-                            drop(user_lock);
-                            if need_insert {
-                                let mut user_write = user_volumes.write().await;
-                                user_write.insert(user_id.to_owned(), 1.);
-                                info!("volume set {} to {}", user_name, 1.);
+                        let user_name = member.display_name.clone();
+                        let key = (user_info.identify.clone(), user_info.user_id);
+                        match user_info.event {
+                            VoiceUserEvent::Join | VoiceUserEvent::Update => {
+                                present.insert(key, guild_id);
+                                overlay
+                                    .join_user(
+                                        &session_id,
+                                        user_info.user_id,
+                                        user_name.clone(),
+                                        member.avatar_url.clone(),
+                                        user_info.identify.clone(),
+                                    )
+                                    .await;
+                            }
+                            VoiceUserEvent::Leave => {
+                                present.remove(&key);
+                                overlay.leave_user(&session_id, user_info.user_id).await;
                             }
                         }
+                        let volume = user_volumes.read().await.get(&user_info.identify, user_id);
+                        let emit_data =
+                            EmitData::new(session_id.clone(), user_info, member, volume);
+                        emit_user_data(&emitter, emit_data);
                         // let user_id = UserId::new(user_info.user_id.0);
                         // let user = http.get_user(user_id).await;
                         // if let Ok(user) = user {
@@ -113,24 +250,104 @@ impl VoiceManager {
                         // println!("user:{user_info.user_id:?} has {user_info.event:?} from {user_info.identify:?}");
                     }
                     SendEnum::VoiceData(u) => {
-                        let user_volumes = user_volumes.read().await;
-                        let volume = match user_volumes.get(&UserId::from(u.user_id.0)) {
-                            Some(v) => *v,
-                            None => {
-                                unreachable!()
-                            }
+                        // 設定が無いユーザーはtrackや全体の既定の音量
+                        let volume = user_volumes
+                            .read()
+                            .await
+                            .get(&u.identify, UserId::from(u.user_id.0));
+                        // このtrackが流れるlistenerとgain
+                        let targets: Vec<(SubIdentify, f32)> = {
+                            let routes = routes.read().await;
+                            routes
+                                .iter()
+                                .filter(|route| route.track == u.identify)
+                                .map(|route| (route.listener.clone(), route.gain))
+                                .collect()
                         };
-                        let pcm = convert_voice_data(u.voice_data, volume);
-                        tx.send(pcm).await.unwrap();
+                        for (listener, gain) in targets {
+                            let tx = outputs.read().await.get(&listener).cloned();
+                            if let Some(tx) = tx {
+                                let pcm = convert_voice_data(&u.voice_data, volume * gain);
+                                // 待たずに積む(遅れている分はbackpressureに従って捨てる)
+                                tx.send(pcm);
+                            }
+                        }
+                    }
+                    SendEnum::SpeakingState(speaking_info) => {
+                        overlay
+                            .set_speaking(
+                                &session_id,
+                                speaking_info.user_id,
+                                speaking_info.speaking,
+                            )
+                            .await;
                     }
                 }
             }
         });
     }
-    pub async fn update_volume(&self, user_id: UserId, volume: f32) {
+    pub async fn update_volume(&self, track: Option<&PubIdentify>, user_id: UserId, volume: f32) {
         let user_volume = self.user_volumes.clone();
         let mut writer = user_volume.write().await;
-        writer.insert(user_id, volume);
+        writer.set(track, user_id, volume);
         info!("uesr:{} volume updated to {}", user_id, volume);
     }
+    pub async fn update_route(&self, track: PubIdentify, listener: SubIdentify, gain: f32) {
+        let mut routes = self.routes.write().await;
+        match routes
+            .iter_mut()
+            .find(|route| route.track == track && route.listener == listener)
+        {
+            Some(route) => route.gain = gain,
+            None => routes.push(Route {
+                track: track.clone(),
+                listener: listener.clone(),
+                gain,
+            }),
+        }
+        info!("route {} -> {} gain updated to {}", track, listener, gain);
+    }
+    // listenerの送信先を作る(failoverで作り直してもmetricsは引き継ぐ)
+    pub async fn open_output(&self, listener: SubIdentify) -> VoiceReceiverType {
+        let mut outputs = self.outputs.write().await;
+        let metrics = outputs
+            .get(&listener)
+            .map(|tx| tx.metrics())
+            .unwrap_or_default();
+        let (tx, rx) = relay_channel(self.backpressure, metrics);
+        outputs.insert(listener, tx);
+        rx
+    }
+    pub fn input_metrics(&self, track: &PubIdentify) -> Arc<RelayMetrics> {
+        let mut inputs = self.inputs.lock().unwrap();
+        inputs.entry(track.clone()).or_default().clone()
+    }
+    pub async fn relay_stats(&self) -> RelayReport {
+        let tracks = {
+            let inputs = self.inputs.lock().unwrap();
+            inputs
+                .iter()
+                .map(|(track, metrics)| TrackStats {
+                    track: track.clone(),
+                    dropped_frames: metrics.dropped_frames(),
+                })
+                .collect()
+        };
+        let outputs = self.outputs.read().await;
+        let listeners = outputs
+            .iter()
+            .map(|(listener, tx)| tx.stats(listener.clone()))
+            .collect();
+        RelayReport { tracks, listeners }
+    }
+    // 送信先をdropしてlistenerの再生taskを終わらせる
+    pub async fn clear_outputs(&self) {
+        self.outputs.write().await.clear();
+    }
+    pub async fn get_routes(&self) -> Vec<Route> {
+        self.routes.read().await.clone()
+    }
+    pub async fn clear_overlay(&self) {
+        self.overlay.clear(&self.session_id).await;
+    }
 }
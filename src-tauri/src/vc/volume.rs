@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serenity::all::UserId;
+
+use super::{config::SessionConfig, types::PubIdentify};
+
+// trackごとの音量設定
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TrackVolume {
+    // このtrackのユーザーの既定の音量
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<f32>,
+    // このtrackでのユーザーごとの音量
+    #[serde(default)]
+    pub users: HashMap<UserId, f32>,
+}
+
+// (track, user)ごとの音量
+// (track, user) > userの既定 > trackの既定 > 最後に合わせた音量 > 全体の既定 の順に探す
+#[derive(Clone, Debug)]
+pub struct Volumes {
+    pub tracks: HashMap<PubIdentify, TrackVolume>,
+    pub users: HashMap<UserId, f32>,
+    pub last: HashMap<UserId, f32>,
+    pub default: f32,
+}
+
+impl Volumes {
+    pub fn from_session(session: &SessionConfig) -> Self {
+        Volumes {
+            tracks: session.track_volumes.clone(),
+            users: session.user_volumes.clone(),
+            last: session.last_volumes.clone(),
+            default: session.default_volume.unwrap_or(1.),
+        }
+    }
+    pub fn get(&self, track: &PubIdentify, user_id: UserId) -> f32 {
+        let track_volume = self.tracks.get(track);
+        track_volume
+            .and_then(|t| t.users.get(&user_id))
+            .or_else(|| self.users.get(&user_id))
+            .or_else(|| track_volume.and_then(|t| t.default.as_ref()))
+            .or_else(|| self.last.get(&user_id))
+            .copied()
+            .unwrap_or(self.default)
+    }
+    // trackを指定した場合はそのtrackでの音量だけを変える
+    // (最後に合わせた音量は，trackの既定が無いtrackに移ったときに使う)
+    pub fn set(&mut self, track: Option<&PubIdentify>, user_id: UserId, volume: f32) {
+        match track {
+            Some(track) => {
+                let track_volume = self.tracks.entry(track.clone()).or_default();
+                track_volume.users.insert(user_id, volume);
+                self.last.insert(user_id, volume);
+            }
+            None => {
+                self.users.insert(user_id, volume);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_default_wins_over_last_volume() {
+        let track1 = PubIdentify("Track1".to_owned());
+        let track2 = PubIdentify("Track2".to_owned());
+        let track3 = PubIdentify("Track3".to_owned());
+        let user = UserId::new(42);
+        let mut volumes = Volumes {
+            tracks: HashMap::from([(
+                track2.clone(),
+                TrackVolume {
+                    default: Some(0.5),
+                    users: HashMap::new(),
+                },
+            )]),
+            users: HashMap::new(),
+            last: HashMap::new(),
+            default: 1.,
+        };
+        volumes.set(Some(&track1), user, 2.);
+        assert_eq!(volumes.get(&track1, user), 2.);
+        // Track2の既定は上書きしない
+        assert_eq!(volumes.get(&track2, user), 0.5);
+        // 既定が無いtrackでは最後に合わせた音量
+        assert_eq!(volumes.get(&track3, user), 2.);
+        // trackを指定しない場合はuserの既定
+        volumes.set(None, user, 1.5);
+        assert_eq!(volumes.get(&track2, user), 1.5);
+        assert_eq!(volumes.get(&track1, user), 2.);
+    }
+
+    #[test]
+    fn lookup_falls_back_in_order() {
+        let track = PubIdentify("Track1".to_owned());
+        let user = UserId::new(42);
+        let mut volumes = Volumes {
+            tracks: HashMap::from([(
+                track.clone(),
+                TrackVolume {
+                    default: None,
+                    users: HashMap::new(),
+                },
+            )]),
+            users: HashMap::new(),
+            last: HashMap::new(),
+            default: 0.1,
+        };
+        // 上の段から1つずつ設定を消していく
+        volumes
+            .tracks
+            .get_mut(&track)
+            .unwrap()
+            .users
+            .insert(user, 0.5);
+        volumes.users.insert(user, 0.4);
+        volumes.tracks.get_mut(&track).unwrap().default = Some(0.3);
+        volumes.last.insert(user, 0.2);
+        assert_eq!(volumes.get(&track, user), 0.5);
+        volumes.tracks.get_mut(&track).unwrap().users.clear();
+        assert_eq!(volumes.get(&track, user), 0.4);
+        volumes.users.clear();
+        assert_eq!(volumes.get(&track, user), 0.3);
+        volumes.tracks.get_mut(&track).unwrap().default = None;
+        assert_eq!(volumes.get(&track, user), 0.2);
+        volumes.last.clear();
+        assert_eq!(volumes.get(&track, user), 0.1);
+    }
+}
@@ -17,17 +17,52 @@ import { debounce } from "lodash";
 import {getVersion} from "@tauri-apps/api/app";
 
 type VcType = { id: string; name: string };
+type BotStatusType = {
+  session: string;
+  role: "speaker" | "listener";
+  name: string;
+  state: "connecting" | "ready" | "in_channel" | "reconnecting" | "failed" | "stopped";
+};
+const statusKey = (role: string, name: string) => `${role}:${name}`;
+// コマンドのエラー(codeで種類を判別できる)
+type CommandErrorType = { code: string; message: string };
+const errorMessage = (e: unknown) =>
+  typeof e === "object" && e !== null && "message" in e
+    ? (e as CommandErrorType).message
+    : String(e);
+type VoiceChannelsType = {
+  tracks: Record<string, VcType[]>;
+  listeners: Record<string, VcType[]>;
+};
+// 試合ごとに保存したチャンネル選択
+type MatchPresetType = {
+  name: string;
+  speakers: Record<string, string>;
+  listeners: Record<string, string>;
+  labels: Record<string, string>;
+  listening: string[];
+};
+// 大会スケジュール(currentは今の試合のid)
+type ScheduledMatchType = {
+  id: string;
+  speakers: Record<string, string>;
+  listeners: Record<string, string>;
+  teams: Record<string, string>;
+};
+type ScheduleType = { matches: ScheduledMatchType[]; current: string | null };
 
 function LabelSelect({
+  value,
   setChannelId,
   vcs,
 }: {
-  setChannelId: React.Dispatch<React.SetStateAction<string>>;
+  value: string;
+  setChannelId: (value: string) => void;
   vcs: VcType[];
 }) {
   return (
     <form className="max-w-sm mx-auto">
-      <Select onValueChange={(value) => setChannelId(value)}>
+      <Select value={value} onValueChange={(value) => setChannelId(value)}>
         <SelectTrigger className="mx-auto text-center text-lg">
           <SelectValue placeholder="Select a channel" />
         </SelectTrigger>
@@ -46,34 +81,67 @@ function LabelSelect({
     </form>
   );
 }
-type IdentifyType = "Track1" | "Track2";
+type IdentifyType = string;
 type EmitDataType = {
+  session: string;
   user_id: string;
-  event: "Join" | "Leave";
+  // Updateは名前やアイコンが後から分かったとき
+  event: "Join" | "Leave" | "Update";
   identify: IdentifyType;
+  // このTrackでの音量(1.0が100%)
+  volume: number;
+  // ニックネーム > 表示名 > ユーザー名
   name: string;
+  username: string;
+  nick: string | null;
+  global_name: string | null;
+  avatar_url: string | null;
 };
 
+// user_idごと(名前は後から変わることがある)
 type PubUserStateType = Map<
   string,
   {
-    user_id: string;
+    name: string;
+    username: string;
+    avatar_url: string | null;
     volume: number;
   }
 >;
-const Users = ({ identify,updater }: { identify: "Track1" | "Track2",updater:boolean }) => {
+const Users = ({
+  session,
+  identify,
+  updater,
+  anchor,
+  setAnchor,
+}: {
+  session: string;
+  identify: IdentifyType;
+  updater: boolean;
+  anchor?: string;
+  setAnchor: (user_id: string | null) => void;
+}) => {
   // UserのVC Sliderをリセットするために，強制Re-render用のupdater
   const [pubUsers, setPubUsers] = useState<PubUserStateType>(new Map());
   const emitFn = (emit_data: EmitDataType) => {
-    const { user_id, name } = emit_data;
-    if (emit_data.event === "Join" && emit_data.identify === identify) {
+    const { user_id, name, username, avatar_url, volume } = emit_data;
+    if (emit_data.session !== session) return;
+    if (
+      (emit_data.event === "Join" || emit_data.event === "Update") &&
+      emit_data.identify === identify
+    ) {
       setPubUsers((users) => {
-        users.set(name, { user_id, volume: 100 });
+        users.set(user_id, {
+          name,
+          username,
+          avatar_url,
+          volume: Math.round(volume * 100),
+        });
         return new Map(users);
       });
     } else if (emit_data.event === "Leave" && emit_data.identify === identify) {
       setPubUsers((users) => {
-        users.delete(name);
+        users.delete(user_id);
         return new Map(users);
       });
     }
@@ -98,30 +166,54 @@ const Users = ({ identify,updater }: { identify: "Track1" | "Track2",updater:boo
   }, []);
   const debounceTime = 300;
   const onChangeVolume = useCallback(
-    debounce((value: number[], name: string) => {
+    debounce((value: number[], user_id: string) => {
       const volume = value[0];
       setPubUsers((users) => {
-        let old = pubUsers.get(name);
+        let old = pubUsers.get(user_id);
         // なんかエラー出たらそのまま返す
         if (!old) return pubUsers;
-        users.set(name, { ...old, volume: volume });
+        users.set(user_id, { ...old, volume: volume });
         return new Map(users);
       });
       invoke("update_volume", {
-        user_id: pubUsers.get(name)?.user_id,
+        session_id: session,
+        track: identify,
+        user_id,
         volume: volume / 100,
-      });
+      }).catch(console.error);
     },debounceTime),
     [pubUsers]
   );
-  const UserIds = Array.from(pubUsers.keys()).map((name) => {
+  const UserIds = Array.from(pubUsers.keys()).map((user_id) => {
     return (
-      <div key={name} className="mt-5">
+      <div key={user_id} className="mt-5">
         <div className="mx-5 relative">
-          <p className="text-left">{name}</p>
+          <div className="flex justify-between items-center">
+            <div className="flex items-center gap-2">
+              {pubUsers.get(user_id)?.avatar_url && (
+                <img
+                  src={pubUsers.get(user_id)?.avatar_url ?? undefined}
+                  className="w-6 h-6 rounded-full"
+                />
+              )}
+              <p className="text-left" title={pubUsers.get(user_id)?.username}>
+                {pubUsers.get(user_id)?.name}
+              </p>
+            </div>
+            {/* このユーザーが別のVCに移ったらBotも移る */}
+            <Button
+              size="sm"
+              variant={anchor === user_id ? "default" : "outline"}
+              onClick={() => {
+                setAnchor(anchor === user_id ? null : user_id);
+              }}
+            >
+              Follow
+            </Button>
+          </div>
           <Slider
-            onValueChange={(value) => onChangeVolume(value, name)}
-            defaultValue={[100]}
+            onValueChange={(value) => onChangeVolume(value, user_id)}
+            defaultValue={[pubUsers.get(user_id)?.volume ?? 100]}
             max={200}
             step={1}
           />
@@ -141,24 +233,35 @@ const Users = ({ identify,updater }: { identify: "Track1" | "Track2",updater:boo
   return <div>{UserIds}</div>;
 };
 
-const Listening = ({ identify }: { identify: IdentifyType }) => {
-  const [listening, setListening] = useState(false);
+const Listening = ({
+  session,
+  identify,
+  listening,
+  setListening,
+}: {
+  session: string;
+  identify: IdentifyType;
+  listening: boolean;
+  setListening: (listening: boolean) => void;
+}) => {
   useEffect(() => {
     type updateListeningType = {
+      session_id: string;
       identify: IdentifyType;
       is_listening: boolean;
     };
     const payload: updateListeningType = {
+      session_id: session,
       identify,
       is_listening: listening,
     };
-    invoke("update_is_listening", payload);
+    invoke("update_is_listening", payload).catch(console.error);
   }, [listening]);
   return (
     <div>
       <p>{listening ? "Now Listening" : "Stopping"}</p>
       <Button
-        onClick={() => setListening((l) => !l)}
+        onClick={() => setListening(!listening)}
         variant="outline"
         size="icon"
       >
@@ -168,58 +271,354 @@ const Listening = ({ identify }: { identify: IdentifyType }) => {
   );
 };
 
-function App() {
-  const [vcs, setVCs] = useState<VcType[]>([]);
-  const [channelId, setChannelId] = useState<string>("");
-  const [channelId2, setChannelId2] = useState<string>("");
-  const [subChannelId, setSubChannelId] = useState<string>("");
+function Session({ session, hidden }: { session: string, hidden: boolean }) {
+  const [vcs, setVCs] = useState<VoiceChannelsType>({ tracks: {}, listeners: {} });
+  const [tracks, setTracks] = useState<IdentifyType[]>([]);
+  const [channelIds, setChannelIds] = useState<Record<IdentifyType, string>>({});
+  const [listeners, setListeners] = useState<string[]>([]);
+  const [listenerChannelIds, setListenerChannelIds] = useState<Record<string, string>>({});
   const [usersUpdater,setUsersUpdater] = useState<boolean>(false);
-  const [version,setVersion] = useState<string>("");
+  const [statuses, setStatuses] = useState<Record<string, BotStatusType["state"]>>({});
+  const [error, setError] = useState<string>("");
+  const [joined, setJoined] = useState<boolean>(false);
+  const [labels, setLabels] = useState<Record<IdentifyType, string>>({});
+  const [listening, setListening] = useState<Record<IdentifyType, boolean>>({});
+  const [presets, setPresets] = useState<MatchPresetType[]>([]);
+  const [presetName, setPresetName] = useState<string>("");
+  const [schedule, setSchedule] = useState<ScheduleType>({ matches: [], current: null });
+  const [scheduleSource, setScheduleSource] = useState<string>("");
+  const [anchors, setAnchors] = useState<Record<IdentifyType, string>>({});
+  const [matchNo, setMatchNo] = useState<string>("");
+  const onError = (e: unknown) => {
+    console.error(e);
+    setError(errorMessage(e));
+  };
+
+  // track/listenerが増えた時も呼ぶので，選択済みのchannelは残す
+  const load = async () => {
+    const voice_channels: VoiceChannelsType = await invoke("get_voice_channels", {
+      session_id: session,
+    });
+    console.log(voice_channels);
+    setVCs(voice_channels);
+    const tracks: IdentifyType[] = await invoke("get_tracks", { session_id: session });
+    setTracks(tracks);
+    setChannelIds((ids) =>
+      Object.fromEntries(
+        tracks.map((track) => [
+          track,
+          ids[track] ?? voice_channels.tracks[track]?.[0]?.id ?? "",
+        ])
+      )
+    );
+    const listeners: string[] = await invoke("get_listeners", { session_id: session });
+    setListeners(listeners);
+    setListenerChannelIds((ids) =>
+      Object.fromEntries(
+        listeners.map((listener) => [
+          listener,
+          ids[listener] ?? voice_channels.listeners[listener]?.[0]?.id ?? "",
+        ])
+      )
+    );
+    const status: BotStatusType[] = await invoke("get_status", { session_id: session });
+    setStatuses(
+      Object.fromEntries(status.map((s) => [statusKey(s.role, s.name), s.state]))
+    );
+    setPresets(await invoke("get_presets", { session_id: session }));
+    setSchedule(await invoke("get_schedule", { session_id: session }));
+    setAnchors(await invoke("get_anchors", { session_id: session }));
+  };
+  useEffect(() => {
+    // 権限が無いなどでチャンネル一覧を取れない場合もエラーを表示する
+    load().catch(onError);
+  }, []);
+  // Botの接続状態
+  useEffect(() => {
+    let unlisten: UnlistenFn;
+    const fn = async () => {
+      unlisten = await listen<BotStatusType>("bot-status-changed", (event) => {
+        const s = event.payload;
+        if (s.session !== session) return;
+        setStatuses((statuses) => ({ ...statuses, [statusKey(s.role, s.name)]: s.state }));
+      });
+    };
+    fn();
+    return () => {
+      if (unlisten) unlisten();
+    };
+  }, []);
 
+  // anchorのユーザーを追ってBotが移ったらチャンネルの選択も変える
   useEffect(() => {
+    type SpeakerFollowedType = { session: string; track: string; channel_id: string };
+    let unlisten: UnlistenFn;
     const fn = async () => {
-      const voice_channels: VcType[] = await invoke("get_voice_channels");
-      console.log(voice_channels);
-      setVCs(voice_channels);
-      setChannelId(voice_channels[0].id);
-      setChannelId2(voice_channels[0].id);
-      setSubChannelId(voice_channels[0].id);
-      getVersion().then(setVersion);
+      unlisten = await listen<SpeakerFollowedType>("speaker-followed", (event) => {
+        const f = event.payload;
+        if (f.session !== session) return;
+        setChannelIds((ids) => ({ ...ids, [f.track]: f.channel_id }));
+      });
     };
     fn();
+    return () => {
+      if (unlisten) unlisten();
+    };
   }, []);
+  const onSetAnchor = (track: string, user_id: string | null) => {
+    invoke("set_anchor", { session_id: session, track, user_id })
+      .then(() => {
+        setError("");
+        setAnchors((anchors) => {
+          const { [track]: _, ...rest } = anchors;
+          return user_id ? { ...rest, [track]: user_id } : rest;
+        });
+      })
+      .catch(onError);
+  };
+
+  // bot_poolのBotを割り当ててtrackを増やす
+  const onAddTrack = async () => {
+    await invoke("add_track", {
+      session_id: session,
+      track: `Track${tracks.length + 1}`,
+    }).then(() => setError("")).catch(onError);
+    await load().catch(onError);
+  };
 
   const onJoin = async () => {
-    console.log(channelId, channelId2, subChannelId);
+    console.log(channelIds, listenerChannelIds);
     await invoke("join", {
-      ch1: channelId,
-      ch2: channelId2,
-      sub_ch: subChannelId,
-    });
+      session_id: session,
+      speaker_chs: channelIds,
+      listener_chs: listenerChannelIds,
+    })
+      .then(() => setError(""))
+      .catch(onError)
+      // 一部のBotが入れなくてもJoin中として扱う
+      .finally(() => setJoined(true));
   };
   const cleanUpUsers = () => {
     setUsersUpdater(u => !u);
   };
+  // Join中はLeaveせずにそのBotだけチャンネルを移す
+  const switchChannel = (role: BotStatusType["role"], name: string, channelId: string) => {
+    if (!joined) return;
+    invoke("switch_channel", {
+      session_id: session,
+      role,
+      name,
+      channel_id: channelId,
+    })
+      .then(() => setError(""))
+      .catch(onError);
+  };
+  // 今の選択をpresetとして保存する(同じ名前は上書き)
+  const onSavePreset = async () => {
+    if (!presetName) return;
+    await invoke("save_preset", {
+      session_id: session,
+      name: presetName,
+      speaker_chs: channelIds,
+      listener_chs: listenerChannelIds,
+      labels,
+      listening: tracks.filter((track) => listening[track]),
+    })
+      .then(() => setError(""))
+      .catch(onError);
+    setPresets(await invoke("get_presets", { session_id: session }));
+  };
+  const onDeletePreset = async () => {
+    await invoke("delete_preset", { session_id: session, name: presetName })
+      .then(() => setError(""))
+      .catch(onError);
+    setPresets(await invoke("get_presets", { session_id: session }));
+  };
+  // 選択中のチャンネル，ラベル，聞き取り状態をpresetに合わせる
+  const showPreset = (preset: MatchPresetType) => {
+    setChannelIds((ids) => ({ ...ids, ...preset.speakers }));
+    setListenerChannelIds((ids) => ({ ...ids, ...preset.listeners }));
+    setLabels(preset.labels);
+    setListening(
+      Object.fromEntries(tracks.map((track) => [track, preset.listening.includes(track)]))
+    );
+  };
+  // presetのチャンネルにJoinする(Join中なら変わるBotだけ移る)
+  const onApplyPreset = async (name: string) => {
+    const preset = presets.find((p) => p.name === name);
+    if (!preset) return;
+    setPresetName(name);
+    showPreset(preset);
+    await invoke("apply_preset", { session_id: session, name })
+      .then(() => setError(""))
+      .catch(onError)
+      .finally(() => setJoined(true));
+  };
+  // 試合番号からチャンネルを探してJoinする(configのdiscovery)
+  const onDiscoverMatch = async () => {
+    if (!matchNo) return;
+    try {
+      const preset: MatchPresetType = await invoke("discover_match", {
+        session_id: session,
+        match_no: matchNo,
+      });
+      showPreset(preset);
+      await invoke("apply_channels", { session_id: session, preset }).finally(() =>
+        setJoined(true)
+      );
+      setError("");
+    } catch (e) {
+      onError(e);
+    }
+  };
+  const onImportSchedule = async () => {
+    if (!scheduleSource) return;
+    await invoke<ScheduleType>("import_schedule", { session_id: session, source: scheduleSource })
+      .then((schedule) => {
+        setError("");
+        setSchedule(schedule);
+      })
+      .catch(onError);
+  };
+  // スケジュールの試合に移る(match_idが無ければ次の試合)
+  const onGoToMatch = async (match_id?: string) => {
+    await (match_id
+      ? invoke("go_to_match", { session_id: session, match_id })
+      : invoke("next_match", { session_id: session })
+    )
+      .then(() => setError(""))
+      .catch(onError);
+    const schedule: ScheduleType = await invoke("get_schedule", { session_id: session });
+    setSchedule(schedule);
+    const current = schedule.matches.find((m) => m.id === schedule.current);
+    if (!current) return;
+    setJoined(true);
+    showPreset({
+      name: current.id,
+      speakers: current.speakers,
+      listeners: current.listeners,
+      labels: current.teams,
+      listening: Object.keys(current.speakers),
+    });
+  };
   return (
-    <main className="text-center">
-      <h1 className="text-3xl font-black my-2">Welcome to DiscordVoiceComm v{version}</h1>
+    <div className={hidden ? "hidden" : ""}>
+      <div className="mt-5 flex justify-center items-center gap-2">
+        {presets.map((preset) => (
+          <Button
+            key={preset.name}
+            variant={preset.name === presetName ? "default" : "outline"}
+            onClick={() => onApplyPreset(preset.name)}
+          >
+            {preset.name}
+          </Button>
+        ))}
+        <input
+          className="border rounded px-2 py-1"
+          placeholder="Preset name"
+          value={presetName}
+          onChange={(e) => setPresetName(e.target.value)}
+        />
+        <Button variant="outline" onClick={onSavePreset}>
+          Save
+        </Button>
+        <Button variant="outline" onClick={onDeletePreset}>
+          Delete
+        </Button>
+      </div>
+      <div className="mt-3 flex justify-center items-center gap-2">
+        <input
+          className="border rounded px-2 py-1 w-24"
+          placeholder="Match #"
+          value={matchNo}
+          onChange={(e) => setMatchNo(e.target.value)}
+          onKeyDown={(e) => e.key === "Enter" && onDiscoverMatch()}
+        />
+        <Button variant="outline" onClick={onDiscoverMatch}>
+          Go
+        </Button>
+        <input
+          className="border rounded px-2 py-1"
+          placeholder="Schedule file, URL, startgg:… or challonge:…"
+          value={scheduleSource}
+          onChange={(e) => setScheduleSource(e.target.value)}
+        />
+        <Button variant="outline" onClick={onImportSchedule}>
+          Import
+        </Button>
+        {schedule.matches.length > 0 && (
+          <>
+            <select
+              className="border rounded px-2 py-1"
+              value={schedule.current ?? ""}
+              onChange={(e) => onGoToMatch(e.target.value)}
+            >
+              <option value="" disabled>
+                Match
+              </option>
+              {schedule.matches.map((m) => (
+                <option key={m.id} value={m.id}>
+                  {m.id} {Object.keys(m.teams).sort().map((track) => m.teams[track]).join(" vs ")}
+                </option>
+              ))}
+            </select>
+            <Button onClick={() => onGoToMatch()}>Next Match</Button>
+          </>
+        )}
+      </div>
       <div className="mt-5 mx-auto font-bold text-lg">
-        <p>Listener</p>
-        <LabelSelect setChannelId={setSubChannelId} vcs={vcs} />
+        {listeners.map((listener) => (
+          <div key={listener}>
+            <p>{listener}</p>
+            <p className="text-sm font-normal">{statuses[statusKey("listener", listener)] ?? ""}</p>
+            <LabelSelect
+              value={listenerChannelIds[listener] ?? ""}
+              setChannelId={(value) => {
+                setListenerChannelIds((ids) => ({ ...ids, [listener]: value }));
+                switchChannel("listener", listener, value);
+              }}
+              vcs={vcs.listeners[listener] ?? []}
+            />
+          </div>
+        ))}
       </div>
-      <div className="grid grid-cols-2 mt-8">
-        <div className=" font-bold text-lg">
-          <Listening identify="Track1" />
-          <p>Speaker1</p>
-          <LabelSelect setChannelId={setChannelId} vcs={vcs} />
-          <Users identify="Track1" updater={usersUpdater} />
-        </div>
-        <div className="flex-auto font-bold text-lg">
-          <Listening identify="Track2" />
-          <p>Speaker2</p>
-          <LabelSelect setChannelId={setChannelId2} vcs={vcs} />
-          <Users identify="Track2" updater={usersUpdater} />
-        </div>
+      <div
+        className="grid mt-8"
+        style={{ gridTemplateColumns: `repeat(${tracks.length}, minmax(0, 1fr))` }}
+      >
+        {tracks.map((track) => (
+          <div key={track} className="font-bold text-lg">
+            <Listening
+              session={session}
+              identify={track}
+              listening={listening[track] ?? false}
+              setListening={(l) => setListening((ls) => ({ ...ls, [track]: l }))}
+            />
+            <p>{track}</p>
+            <input
+              className="border rounded px-2 text-center text-base font-normal"
+              placeholder="Label"
+              value={labels[track] ?? ""}
+              onChange={(e) => setLabels((ls) => ({ ...ls, [track]: e.target.value }))}
+            />
+            <p className="text-sm font-normal">{statuses[statusKey("speaker", track)] ?? ""}</p>
+            <LabelSelect
+              value={channelIds[track] ?? ""}
+              setChannelId={(value) => {
+                setChannelIds((ids) => ({ ...ids, [track]: value }));
+                switchChannel("speaker", track, value);
+              }}
+              vcs={vcs.tracks[track] ?? []}
+            />
+            <Users
+              session={session}
+              identify={track}
+              updater={usersUpdater}
+              anchor={anchors[track]}
+              setAnchor={(user_id) => onSetAnchor(track, user_id)}
+            />
+          </div>
+        ))}
       </div>
       <div className="mt-10">
         <Button className="mx-5" onClick={onJoin}>
@@ -227,11 +626,63 @@ function App() {
         </Button>
         <Button
           className="mx-5"
-          onClick={() => invoke("leave").then(cleanUpUsers).catch(console.error)}
+          onClick={() =>
+            invoke("leave", { session_id: session })
+              .then(() => {
+                setError("");
+                setJoined(false);
+                cleanUpUsers();
+              })
+              .catch(onError)
+          }
         >
           <p className="text-lg px-4 font-bold">Leave</p>
         </Button>
+        <Button className="mx-5" variant="outline" onClick={onAddTrack}>
+          <p className="text-lg px-4 font-bold">+ Track</p>
+        </Button>
       </div>
+      {error && <p className="mt-3 text-red-500">{error}</p>}
+    </div>
+  );
+}
+
+function App() {
+  const [sessions, setSessions] = useState<string[]>([]);
+  const [selected, setSelected] = useState<string>("");
+  const [version,setVersion] = useState<string>("");
+
+  useEffect(() => {
+    const fn = async () => {
+      const sessions: string[] = await invoke("get_sessions");
+      setSessions(sessions);
+      setSelected(sessions[0] ?? "");
+      getVersion().then(setVersion);
+    };
+    fn();
+  }, []);
+
+  return (
+    <main className="text-center">
+      <h1 className="text-3xl font-black my-2">Welcome to DiscordVoiceComm v{version}</h1>
+      {sessions.length > 1 && (
+        <div className="mt-2">
+          {sessions.map((session) => (
+            <Button
+              key={session}
+              className="mx-1"
+              variant={session === selected ? "default" : "outline"}
+              onClick={() => setSelected(session)}
+            >
+              {session}
+            </Button>
+          ))}
+        </div>
+      )}
+      {/* 切り替えても状態が消えないように全sessionを描画しておく */}
+      {sessions.map((session) => (
+        <Session key={session} session={session} hidden={session !== selected} />
+      ))}
     </main>
   );
 }